@@ -1,4 +1,5 @@
 pub mod files;
 pub mod invitations;
 pub mod sessions;
+pub mod settings;
 pub mod users;
@@ -0,0 +1,32 @@
+use context::{EmailMetricsSnapshot, SenderCapabilities};
+use serde::Serialize;
+
+/// Status of the email configuration on the server,
+/// used to show the health of the email sending in the admin dashboard.
+#[derive(Debug, Clone, Serialize)]
+pub struct EmailStatus {
+    /// Is there an email sender configured on the server
+    pub configured: bool,
+
+    /// TLS mode used for the SMTP connection
+    pub tls_mode: Option<String>,
+
+    /// Address of the SMTP relay, without any credentials
+    pub address: Option<String>,
+
+    /// Result of the connection probe against the relay
+    pub connected: bool,
+
+    /// Error returned by the connection probe, if any
+    pub error: Option<String>,
+
+    /// The from address is configured with the deprecated SMTP_DEFAULT_FROM,
+    /// only the flag is exposed, never the address itself
+    pub deprecated_default_from: bool,
+
+    /// What the configured sender supports, e.g. to hide the options it can't do
+    pub capabilities: Option<SenderCapabilities>,
+
+    /// Number of sent and failed emails since the server started
+    pub metrics: EmailMetricsSnapshot,
+}
@@ -0,0 +1,3 @@
+pub mod email_status;
+pub mod test_email;
+pub mod test_email_bulk;
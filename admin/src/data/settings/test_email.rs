@@ -0,0 +1,32 @@
+use error::{AppResult, Error};
+use serde::Deserialize;
+use validr::error::{ValidationError, ValidationErrors};
+
+/// Optional body of the test email request
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TestEmail {
+    /// Consider the test failed if the configuration produced any warnings,
+    /// even when the email itself was sent successfully.
+    pub strict: Option<bool>,
+}
+
+impl TestEmail {
+    /// In strict mode, fail with the list of active configuration warnings
+    pub fn check_warnings(&self, warnings: &[String]) -> AppResult<()> {
+        if !self.strict.unwrap_or(false) || warnings.is_empty() {
+            return Ok(());
+        }
+
+        let mut error = ValidationError::new();
+        error.set_field_name("config");
+
+        for warning in warnings {
+            error.add(warning);
+        }
+
+        let mut errors = ValidationErrors::new();
+        errors.add(error);
+
+        Err(Error::Validation(errors))
+    }
+}
@@ -0,0 +1,87 @@
+use ::error::AppResult;
+use context::SendOutcome;
+use serde::{Deserialize, Serialize};
+use validr::*;
+
+/// Most addresses the test email can be sent to with a single request
+pub const MAX_TEST_EMAIL_RECIPIENTS: usize = 10;
+
+/// Request to send the test email to several addresses at once,
+/// e.g. to check the delivery to every provider the users are on
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TestEmailBulk {
+    /// Addresses the test email is sent to, every one gets its own email
+    pub to: Option<Vec<String>>,
+}
+
+impl Validation for TestEmailBulk {
+    fn rules(&self) -> Vec<Rule<Self>> {
+        vec![Rule::new("to", |obj: &Self, error| {
+            let to = obj.to.as_deref().unwrap_or_default();
+
+            if to.is_empty() {
+                error.add("required");
+            }
+
+            if to.len() > MAX_TEST_EMAIL_RECIPIENTS {
+                error.add(format!("max:{MAX_TEST_EMAIL_RECIPIENTS}").as_str());
+            }
+
+            for address in to {
+                if !validr::helpers::email::validate_email(address.as_str()) {
+                    error.add(format!("email:{address}").as_str());
+                }
+            }
+        })]
+    }
+
+    fn modifiers(&self) -> Vec<Modifier<Self>> {
+        vec![Modifier::new("to", |obj: &mut Self| {
+            if let Some(to) = obj.to.take() {
+                let mut addresses: Vec<String> = vec![];
+
+                // The same address twice would only get the same email twice
+                for address in to.iter().map(|address| address.trim().to_lowercase()) {
+                    if !addresses.contains(&address) {
+                        addresses.push(address);
+                    }
+                }
+
+                obj.to = Some(addresses);
+            }
+        })]
+    }
+}
+
+impl TestEmailBulk {
+    /// Validated addresses the test email is sent to
+    pub fn into_addresses(self) -> AppResult<Vec<String>> {
+        Ok(self.validate()?.to.unwrap_or_default())
+    }
+}
+
+/// Outcome of the test email sent to one of the addresses
+#[derive(Debug, Clone, Serialize)]
+pub struct TestEmailBulkResult {
+    pub address: String,
+
+    /// Was the email accepted for delivery
+    pub sent: bool,
+
+    /// Why the email wasn't sent
+    pub error: Option<String>,
+
+    /// Reply of the relay to the email, e.g. `250 2.0.0 Ok: queued`
+    pub relay_response: Option<String>,
+}
+
+impl TestEmailBulkResult {
+    pub fn new(address: String, outcome: SendOutcome) -> Self {
+        Self {
+            address,
+            sent: outcome.sent,
+            error: outcome.error,
+            relay_response: outcome.relay_response.map(|response| response.to_string()),
+        }
+    }
+}
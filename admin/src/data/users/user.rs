@@ -13,6 +13,7 @@ pub struct User {
     pub pubkey: String,
     pub fingerprint: String,
     pub email_verified_at: Option<i64>,
+    pub email_notifications: bool,
     pub created_at: i64,
     pub updated_at: i64,
     pub last_session: Option<Session>,
@@ -34,6 +35,7 @@ impl FromQueryResult for User {
             pubkey: user.pubkey,
             fingerprint: user.fingerprint,
             email_verified_at: user.email_verified_at,
+            email_notifications: user.email_notifications_disabled_at.is_none(),
             created_at: user.created_at,
             updated_at: user.updated_at,
             last_session,
@@ -1,10 +1,12 @@
-use context::{Context, SenderContract};
-use entity::invitations;
+use context::{outbox::OutboxRepository, Context, SenderContract};
+use entity::{invitations, ConnectionTrait};
 use error::{AppResult, Error};
 
-/// Send an invitation email to the provided email address
-pub(crate) async fn send(
+/// Queue an invitation email to the provided email address, the email is stored
+/// through the given connection so it is only sent if the invitation is stored too.
+pub(crate) async fn send<T: ConnectionTrait>(
     context: &Context,
+    connection: &T,
     invitation: &invitations::Model,
     message: Option<String>,
 ) -> AppResult<()> {
@@ -19,8 +21,12 @@ pub(crate) async fn send(
 
     let content = r#"
     <h1>You have been invited to join the {{app_name}}</h1>
-        {{message}}
-        {{role}}
+    {{#if message}}
+    <p>{{message}}</p>
+    {{/if}}
+    {{#if role}}
+    <p>With role: {{role}}</p>
+    {{/if}}
     <p>
         This invitation is valid until: {{expires_at}}
     </p>
@@ -62,19 +68,20 @@ pub(crate) async fn send(
     template.add_template_var("link", &link);
 
     if let Some(message) = message {
-        template.add_template_var("message", format!("<p>{message}</p>").as_str());
+        template.add_template_var("message", message);
     }
 
     if let Some(role) = invitation.role.as_deref() {
-        template.add_template_var("role", format!("<p>With role: {role}</p>").as_str());
+        template.add_template_var("role", role);
     }
 
     template.add_template_var("app_name", &app_name);
     template.add_template_var("expires_at", &expires_at);
-    template.register_content_template(content.as_str())?;
+    template.register_named_content_template("invitation", content.as_str())?;
 
-    sender
-        .send(vec![template.to(&invitation.email)?])
-        .await
-        .map(|_| ())
+    OutboxRepository::new(connection)
+        .enqueue(&template.to(&invitation.email)?)
+        .await?;
+
+    Ok(())
 }
@@ -1 +1,2 @@
 pub(crate) mod invite;
+pub mod test_email;
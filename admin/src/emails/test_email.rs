@@ -0,0 +1,72 @@
+use config::Config;
+use context::{SendOutcome, SenderContract, Template};
+use error::AppResult;
+
+/// Send the test email that verifies the email configuration to the given address,
+/// this is used by the admin settings and by the `send-test-email` command.
+pub async fn send<S: SenderContract + ?Sized>(
+    sender: &S,
+    config: &Config,
+    to: &str,
+) -> AppResult<()> {
+    sender.send_one(template(sender, config)?.to(to)?).await
+}
+
+/// Send the test email to every one of the addresses in a single batch,
+/// the outcomes are in the same order as the addresses
+pub async fn send_bulk<S: SenderContract + ?Sized>(
+    sender: &S,
+    config: &Config,
+    to: &[String],
+) -> AppResult<Vec<SendOutcome>> {
+    let template = template(sender, config)?;
+
+    let emails = to
+        .iter()
+        .map(|address| template.for_recipient(address))
+        .collect::<AppResult<Vec<Template>>>()?;
+
+    sender.send_outcomes(emails).await
+}
+
+/// Test email without the recipient, its content can be replaced with
+/// `test_email.hbs` or `test-email.html` in MAIL_TEMPLATE_DIR
+fn template<S: SenderContract + ?Sized>(sender: &S, config: &Config) -> AppResult<Template> {
+    let content = r#"
+    <h1>Test Email from {{app_name}}</h1>
+    <p>
+        This is a test email to verify your SMTP configuration is working correctly.
+    </p>
+    <p>
+        If you received this email, your email settings are configured properly!
+    </p>
+    <p>
+        <strong>Configuration details:</strong>
+    </p>
+    <ul>
+        <li>Application: {{app_name}}</li>
+        <li>Version: {{app_version}}</li>
+        <li>Sent at: {{sent_at}}</li>
+    </ul>
+    "#
+    .to_string();
+
+    let app_name = config.get_app_name();
+    let app_version = config.get_app_version();
+    // Strict so the test email never goes out with a placeholder left empty
+    let mut template = sender
+        .template(
+            "Test Email - SMTP Configuration",
+            "This is a test email to verify your SMTP configuration",
+        )?
+        .strict(true);
+
+    let sent_at = template.format_datetime(chrono::Utc::now());
+
+    template.add_template_var("app_name", &app_name);
+    template.add_template_var("app_version", app_version);
+    template.add_template_var("sent_at", &sent_at);
+    template.register_named_content_template("test_email", content.as_str())?;
+
+    Ok(template)
+}
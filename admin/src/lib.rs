@@ -1,7 +1,7 @@
 pub mod data;
 pub mod routes;
 
-pub(crate) mod emails;
+pub mod emails;
 pub(crate) mod repository;
 
 #[cfg(test)]
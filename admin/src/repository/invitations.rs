@@ -125,7 +125,13 @@ where
             .await?
             .ok_or_else(|| Error::NotFound("Invitation not found".to_string()))?;
 
-        invite::send(self.repository.context(), &invitation, message).await?;
+        invite::send(
+            self.repository.context(),
+            self.repository.connection(),
+            &invitation,
+            message,
+        )
+        .await?;
 
         Ok(invitation)
     }
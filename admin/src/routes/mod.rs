@@ -19,5 +19,8 @@ pub fn configure(cfg: &mut actix_web::web::ServiceConfig) {
         .service(settings::index)
         .service(settings::update)
         .service(settings::test_email)
+        .service(settings::test_email_bulk)
+        .service(settings::email_status)
+        .service(settings::email_metrics)
         .service(users::remove_tfa);
 }
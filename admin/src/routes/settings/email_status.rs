@@ -0,0 +1,102 @@
+use actix_web::{http::header, route, web, HttpRequest, HttpResponse};
+use auth::data::staff::Staff;
+use config::email::EmailConfig;
+use context::{Context, EmailMetrics, SenderContract};
+use error::{AppResult, Error};
+
+use crate::data::settings::email_status::EmailStatus;
+
+/// Report whether email is configured and the relay is reachable,
+/// without actually sending any email.
+///
+/// Response: [crate::data::settings::email_status::EmailStatus]
+#[route("/api/admin/settings/email-status", method = "GET")]
+pub(crate) async fn email_status(
+    staff: Staff,
+    context: web::Data<Context>,
+) -> AppResult<HttpResponse> {
+    staff.is_admin_or_err()?;
+
+    let (tls_mode, address, deprecated_default_from) = match &context.config.mailer {
+        EmailConfig::Smtp(c) => (
+            Some(c.tls_mode.as_str().to_string()),
+            Some(format!("{}:{}", sanitize_address(&c.address), c.port)),
+            c.used_deprecated_default_from,
+        ),
+        EmailConfig::Webhook(c) => (None, Some(c.host()), false),
+        EmailConfig::DryRun | EmailConfig::None => (None, None, false),
+    };
+
+    let (connected, error) = match &context.sender {
+        Some(sender) => match sender.test_connection().await {
+            Ok(()) => (true, None),
+            Err(e) => (false, Some(e.to_string())),
+        },
+        None => (false, None),
+    };
+
+    Ok(HttpResponse::Ok().json(EmailStatus {
+        configured: context.config.mailer.is_configured(),
+        tls_mode,
+        address,
+        connected,
+        error,
+        deprecated_default_from,
+        capabilities: context.sender.as_ref().map(|sender| sender.capabilities()),
+        metrics: EmailMetrics::global().snapshot(),
+    }))
+}
+
+/// Email counters in the Prometheus text format, so the email delivery
+/// can be scraped and alerted on when it starts failing.
+///
+/// Prometheus authenticates with the MAIL_METRICS_TOKEN as the bearer token,
+/// without it the metrics are only given to the admins.
+#[route("/api/admin/settings/email-metrics", method = "GET")]
+pub(crate) async fn email_metrics(
+    req: HttpRequest,
+    staff: Option<Staff>,
+    context: web::Data<Context>,
+) -> AppResult<HttpResponse> {
+    let given = bearer_token(&req);
+
+    let valid = match (given, context.config.get_mail_metrics_token()) {
+        (Some(given), Some(token)) => constant_time_eq(given.as_bytes(), token.as_bytes()),
+        _ => false,
+    };
+
+    if !valid {
+        match (staff, given) {
+            (Some(staff), _) => staff.is_admin_or_err()?,
+            (None, Some(_)) => {
+                return Err(Error::Unauthorized("invalid_metrics_token".to_string()))
+            }
+            (None, None) => return Err(Error::Unauthorized("missing_metrics_token".to_string())),
+        }
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(EmailMetrics::global().render()))
+}
+
+/// Bearer token from the Authorization header of the request
+fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Compare without returning early, so the token can't be guessed from the response time
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Strip any user info that might have been pasted into the address
+fn sanitize_address(address: &str) -> &str {
+    address
+        .rsplit_once('@')
+        .map(|(_, host)| host)
+        .unwrap_or(address)
+}
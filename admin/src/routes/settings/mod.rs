@@ -1,7 +1,11 @@
+pub mod email_status;
 pub mod index;
 pub mod test_email;
+pub mod test_email_bulk;
 pub mod update;
 
+pub use email_status::*;
 pub use index::*;
 pub use test_email::*;
+pub use test_email_bulk::*;
 pub use update::*;
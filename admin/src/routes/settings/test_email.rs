@@ -1,17 +1,22 @@
 use actix_web::{route, web, HttpResponse};
 use auth::data::staff::Staff;
-use context::{Context, SenderContract};
-use error::AppResult;
+use context::Context;
+use error::{AppResult, Error};
 
-use crate::repository::Repository;
+use crate::{data::settings::test_email::TestEmail, emails, repository::Repository};
 
-/// Send a test email to the authenticated admin user
+/// Send a test email to the authenticated admin user, the same admin can
+/// send one only every `TEST_EMAIL_COOLDOWN_SECONDS`
 ///
-/// Response: Success message
+/// Request: [crate::data::settings::test_email::TestEmail] (optional)
+///
+/// Response: Success message, in strict mode a validation error
+/// listing the configuration warnings if there are any
 #[route("/api/admin/settings/test-email", method = "POST")]
 pub(crate) async fn test_email(
     staff: Staff,
     context: web::Data<Context>,
+    data: Option<web::Json<TestEmail>>,
 ) -> AppResult<HttpResponse> {
     staff.is_admin_or_err()?;
 
@@ -24,48 +29,27 @@ pub(crate) async fn test_email(
         }
     };
 
+    if let Err(remaining) = context
+        .test_email_cooldown
+        .try_acquire(staff.claims.sub, context.config.get_test_email_cooldown())
+    {
+        return Err(Error::TooManyRequests(format!(
+            "too_soon, retry after {} seconds",
+            remaining.as_secs_f64().ceil() as u64
+        )));
+    }
+
     // Get the user's email from the database
     let user = Repository::new(&context, &context.db)
         .users()
         .get(staff.claims.sub)
         .await?;
 
-    let content = r#"
-    <h1>Test Email from {{app_name}}</h1>
-    <p>
-        This is a test email to verify your SMTP configuration is working correctly.
-    </p>
-    <p>
-        If you received this email, your email settings are configured properly!
-    </p>
-    <p>
-        <strong>Configuration details:</strong>
-    </p>
-    <ul>
-        <li>Application: {{app_name}}</li>
-        <li>Version: {{app_version}}</li>
-        <li>Sent at: {{sent_at}}</li>
-    </ul>
-    "#
-    .to_string();
-
-    let app_name = context.config.get_app_name();
-    let app_version = context.config.get_app_version();
-    let sent_at = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC").to_string();
-
-    let mut template = sender.template(
-        "Test Email - SMTP Configuration",
-        "This is a test email to verify your SMTP configuration",
-    )?;
-
-    template.add_template_var("app_name", &app_name);
-    template.add_template_var("app_version", app_version);
-    template.add_template_var("sent_at", &sent_at);
-    template.register_content_template(content.as_str())?;
-
-    let template = template.to(&user.email)?;
+    emails::test_email::send(sender, &context.config, &user.email).await?;
 
-    sender.send(vec![template]).await?;
+    data.map(|d| d.into_inner())
+        .unwrap_or_default()
+        .check_warnings(context.config.get_warnings())?;
 
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "message": format!("Test email sent successfully to {}", &user.email)
@@ -0,0 +1,57 @@
+use actix_web::{route, web, HttpResponse};
+use auth::data::staff::Staff;
+use context::Context;
+use error::{AppResult, Error};
+
+use crate::{
+    data::settings::test_email_bulk::{TestEmailBulk, TestEmailBulkResult},
+    emails,
+};
+
+/// Send the test email to several addresses at once, e.g. to verify the delivery
+/// to every provider after switching the relay. It shares the cooldown of the single
+/// test email, the same admin can send either one only every `TEST_EMAIL_COOLDOWN_SECONDS`
+///
+/// Request: [crate::data::settings::test_email_bulk::TestEmailBulk]
+///
+/// Response: [Vec<crate::data::settings::test_email_bulk::TestEmailBulkResult>]
+#[route("/api/admin/settings/test-email-bulk", method = "POST")]
+pub(crate) async fn test_email_bulk(
+    staff: Staff,
+    context: web::Data<Context>,
+    data: web::Json<TestEmailBulk>,
+) -> AppResult<HttpResponse> {
+    staff.is_admin_or_err()?;
+
+    let sender = match &context.sender {
+        Some(s) => s,
+        None => {
+            return Ok(HttpResponse::Ok().json(serde_json::json!({
+                "message": "Email is not configured on this server"
+            })));
+        }
+    };
+
+    // Invalid requests don't use up the cooldown
+    let addresses = data.into_inner().into_addresses()?;
+
+    if let Err(remaining) = context
+        .test_email_cooldown
+        .try_acquire(staff.claims.sub, context.config.get_test_email_cooldown())
+    {
+        return Err(Error::TooManyRequests(format!(
+            "too_soon, retry after {} seconds",
+            remaining.as_secs_f64().ceil() as u64
+        )));
+    }
+
+    let outcomes = emails::test_email::send_bulk(sender, &context.config, &addresses).await?;
+
+    let results = addresses
+        .into_iter()
+        .zip(outcomes)
+        .map(|(address, outcome)| TestEmailBulkResult::new(address, outcome))
+        .collect::<Vec<_>>();
+
+    Ok(HttpResponse::Ok().json(results))
+}
@@ -5,6 +5,7 @@ use crate::repository::Repository;
 mod files;
 mod invitations;
 mod sessions;
+mod settings;
 mod users;
 
 pub(crate) async fn get_repo<'ctx>(context: &'ctx Context) -> Repository<'ctx, DatabaseConnection> {
@@ -0,0 +1,74 @@
+use error::ErrorResponse;
+
+use crate::data::settings::test_email::TestEmail;
+
+#[test]
+fn test_strict_test_email_fails_on_warnings() {
+    let warnings = vec![
+        "SMTP_DEFAULT_FROM is deprecated and will be removed in a future version.".to_string(),
+    ];
+
+    let data = TestEmail { strict: Some(true) };
+    let error = data.check_warnings(&warnings).unwrap_err();
+    let response = ErrorResponse::from(&error);
+
+    assert_eq!(response.status, 422);
+    assert!(response
+        .context
+        .unwrap()
+        .to_string()
+        .contains("SMTP_DEFAULT_FROM is deprecated"));
+}
+
+#[test]
+fn test_test_email_ignores_warnings_when_not_strict() {
+    let warnings = vec!["SMTP_DEFAULT_FROM is deprecated".to_string()];
+
+    assert!(TestEmail::default().check_warnings(&warnings).is_ok());
+    assert!(TestEmail { strict: Some(true) }.check_warnings(&[]).is_ok());
+}
+
+#[test]
+fn test_bulk_test_email_addresses_are_validated() {
+    use crate::data::settings::test_email_bulk::{TestEmailBulk, MAX_TEST_EMAIL_RECIPIENTS};
+
+    let data = TestEmailBulk {
+        to: Some(vec![
+            " John@Gmail.com ".to_string(),
+            "jane@outlook.com".to_string(),
+            "john@gmail.com".to_string(),
+        ]),
+    };
+
+    assert_eq!(
+        data.into_addresses().unwrap(),
+        vec!["john@gmail.com".to_string(), "jane@outlook.com".to_string()]
+    );
+
+    let invalid = TestEmailBulk {
+        to: Some(vec![
+            "john@gmail.com".to_string(),
+            "not-an-email".to_string(),
+        ]),
+    };
+    let response = ErrorResponse::from(&invalid.into_addresses().unwrap_err());
+
+    assert_eq!(response.status, 422);
+    assert!(response
+        .context
+        .unwrap()
+        .to_string()
+        .contains("email:not-an-email"));
+
+    assert!(TestEmailBulk::default().into_addresses().is_err());
+
+    let too_many = TestEmailBulk {
+        to: Some(
+            (0..=MAX_TEST_EMAIL_RECIPIENTS)
+                .map(|i| format!("user{i}@doe.com"))
+                .collect(),
+        ),
+    };
+
+    assert!(too_many.into_addresses().is_err());
+}
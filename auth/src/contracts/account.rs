@@ -84,6 +84,23 @@ where
         Ok(())
     }
 
+    /// Turn the notification emails on or off for the user
+    async fn set_email_notifications(&self, id: Uuid, enabled: bool) -> AppResult<users::Model> {
+        let disabled_at = match enabled {
+            true => None,
+            false => Some(Utc::now().timestamp()),
+        };
+
+        self.update_user(
+            id,
+            users::ActiveModel {
+                email_notifications_disabled_at: ActiveValue::Set(disabled_at),
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
     /// Load the paginated list of users activity (sessions)
     async fn activity(&self, parameters: ActivityQuery) -> AppResult<Paginated<sessions::Model>> {
         let parameters = parameters.validate()?;
@@ -35,7 +35,7 @@ where
         self.email_activation(user).await
     }
 
-    /// Send activation email to the user
+    /// Queue the activation email to the user, the outbox sends it
     async fn email_activation(&self, user: &users::Model) -> AppResult<()> {
         let sender = match &self.ctx().sender {
             Some(s) => s,
@@ -75,11 +75,11 @@ where
             .as_str(),
         )?;
         template.add_template_var("link", &link);
-        template.register_content_template(content.as_str())?;
+        template.register_named_content_template("activation", content.as_str())?;
 
         let template = template.to(&action.email)?;
 
-        sender.send(vec![template]).await?;
+        self.ctx().enqueue_email(template).await?;
 
         Ok(())
     }
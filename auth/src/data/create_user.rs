@@ -117,6 +117,7 @@ impl CreateUser {
             fingerprint: ActiveValue::Set(data.fingerprint.unwrap()),
             encrypted_private_key: ActiveValue::Set(data.encrypted_private_key),
             email_verified_at: ActiveValue::Set(None),
+            email_notifications_disabled_at: ActiveValue::NotSet,
             created_at: ActiveValue::Set(Utc::now().timestamp()),
             updated_at: ActiveValue::Set(Utc::now().timestamp()),
         })
@@ -0,0 +1,23 @@
+//! # Turn the notification emails on or off for the user
+use ::error::AppResult;
+use serde::{Deserialize, Serialize};
+use validr::*;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EmailNotifications {
+    pub enabled: Option<bool>,
+}
+
+impl Validation for EmailNotifications {
+    fn rules(&self) -> Vec<Rule<Self>> {
+        vec![rule_required!(enabled)]
+    }
+}
+
+impl EmailNotifications {
+    pub fn into_value(&self) -> AppResult<bool> {
+        let data = self.clone().validate()?;
+
+        Ok(data.enabled.unwrap())
+    }
+}
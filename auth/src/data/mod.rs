@@ -4,6 +4,7 @@ pub mod change_password;
 pub mod claims;
 pub mod create_user;
 pub mod credentials;
+pub mod email_notifications;
 pub mod resend_activation;
 pub mod signature;
 pub mod staff;
@@ -0,0 +1,27 @@
+use actix_web::{route, web, HttpResponse};
+use context::Context;
+use error::AppResult;
+
+use crate::{
+    auth::Auth,
+    contracts::account::Account,
+    data::{claims::Claims, email_notifications::EmailNotifications},
+};
+
+/// Turn the notification emails on or off for the user,
+/// transactional emails (e.g. account activation) are always sent.
+///
+/// Request: [EmailNotifications]
+#[route("/api/auth/account/email-notifications", method = "POST")]
+pub(crate) async fn email_notifications(
+    context: web::Data<Context>,
+    claims: Claims,
+    data: web::Json<EmailNotifications>,
+) -> AppResult<HttpResponse> {
+    let auth = Auth::new(&context);
+    let enabled = data.into_inner().into_value()?;
+
+    auth.set_email_notifications(claims.sub, enabled).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
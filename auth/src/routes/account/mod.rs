@@ -1,9 +1,11 @@
 pub mod activity;
 pub mod change_password;
+pub mod email_notifications;
 pub mod kill;
 pub mod kill_all;
 
 pub use activity::*;
 pub use change_password::*;
+pub use email_notifications::*;
 pub use kill::*;
 pub use kill_all::*;
@@ -21,6 +21,7 @@ pub mod signature;
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(account::activity);
     cfg.service(account::change_password);
+    cfg.service(account::email_notifications);
     cfg.service(account::kill_all);
     cfg.service(account::kill);
     cfg.service(action::action);
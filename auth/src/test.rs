@@ -8,7 +8,8 @@ use log::debug;
 use crate::{
     auth::Auth,
     contracts::{
-        cookies::Cookies, provider::AuthProvider, register::Register, repository::Repository,
+        account::Account, cookies::Cookies, provider::AuthProvider, register::Register,
+        repository::Repository,
     },
     data::{create_user::CreateUser, credentials::Credentials},
     providers::credentials::CredentialsProvider,
@@ -278,6 +279,9 @@ async fn test_register_and_send_email() {
         panic!("Errored: {:#?}", e);
     }
 
+    // The activation email is queued, it is sent once the outbox is drained
+    context::outbox::drain(&context).await.unwrap();
+
     let id = context
         .sender
         .unwrap()
@@ -313,6 +317,9 @@ async fn test_activate_user() {
         panic!("Errored: {:#?}", e);
     }
 
+    // The activation email is queued, it is sent once the outbox is drained
+    context::outbox::drain(&context).await.unwrap();
+
     let id = context
         .sender
         .as_ref()
@@ -329,6 +336,24 @@ async fn test_activate_user() {
     assert!(activated_user.email_verified_at.is_some());
 }
 
+#[async_std::test]
+async fn test_toggle_email_notifications() {
+    let context = Context::mock_sqlite().await;
+    let auth = create_lib(&context);
+
+    let user = entity::mock::create_user(&context.db, "john@doe.com", None).await;
+
+    assert!(user.email_notifications_enabled());
+
+    let user = auth.set_email_notifications(user.id, false).await.unwrap();
+
+    assert!(!user.email_notifications_enabled());
+
+    let user = auth.set_email_notifications(user.id, true).await.unwrap();
+
+    assert!(user.email_notifications_enabled());
+}
+
 #[async_std::test]
 async fn test_set_cookie_for_both() {
     let context = Context::mock_sqlite().await;
@@ -1,8 +1,10 @@
 use std::fs::{self, DirBuilder};
 
+use chrono::FixedOffset;
 use url::Url;
 
 use crate::{
+    email::{parse_locale, parse_utc_offset, TransferEncoding},
     helpers::{absolute_path, remove_trailing_slash},
     vars::Vars,
 };
@@ -72,6 +74,71 @@ pub struct AppConfig {
     /// if this is left empty it will be automatically filled with the version
     /// from the Cargo.toml file.
     pub version: String,
+
+    /// MAIL_TEMPLATE_DIR directory with email templates that override the built-in ones,
+    /// template named `invitation` is loaded from `{MAIL_TEMPLATE_DIR}/invitation.hbs`
+    ///
+    /// *optional*
+    ///
+    /// default: built-in templates are used
+    pub mail_template_dir: Option<String>,
+
+    /// MAIL_SUBJECT_PREFIX prefix of the subject of every email, e.g. `[Hoodik]`,
+    /// it makes the emails easy to filter in the inbox
+    ///
+    /// *optional*
+    ///
+    /// default: subjects are not prefixed
+    pub mail_subject_prefix: Option<String>,
+
+    /// MAIL_TRANSFER_ENCODING Content-Transfer-Encoding of the body of the emails,
+    /// one of `7bit`, `quoted-printable` or `base64`, for the receiving systems
+    /// that choke on the encoding picked otherwise
+    ///
+    /// *optional*
+    ///
+    /// default: picked for every email by its content
+    pub mail_transfer_encoding: Option<TransferEncoding>,
+
+    /// MAIL_DEFAULT_LOCALE language of the emails, e.g. `de` or `pt-BR`, the named
+    /// templates look for an override in that language first, e.g. `test_email.de.hbs`
+    ///
+    /// *optional*
+    ///
+    /// default: the built in English templates
+    pub mail_default_locale: Option<String>,
+
+    /// MAIL_DEFAULT_UTC_OFFSET fixed offset from UTC the dates in the emails are formatted in,
+    /// `UTC` or an offset like `+02:00`, it doesn't follow the daylight saving time of a timezone
+    ///
+    /// *optional*
+    ///
+    /// default: UTC
+    pub mail_default_utc_offset: Option<FixedOffset>,
+
+    /// TEST_EMAIL_COOLDOWN_SECONDS minimum time between two test emails sent by the same admin,
+    /// set to 0 to disable the limit
+    ///
+    /// *optional*
+    ///
+    /// default: 30
+    pub test_email_cooldown_seconds: u64,
+
+    /// MAIL_SHUTDOWN_GRACE_SECONDS time the email outbox gets on shutdown to finish
+    /// sending the email it is working on, the unsent emails stay queued
+    ///
+    /// *optional*
+    ///
+    /// default: 30
+    pub mail_shutdown_grace_seconds: u64,
+
+    /// MAIL_METRICS_TOKEN bearer token that gives access to the email metrics
+    /// without an admin session, so they can be scraped by Prometheus
+    ///
+    /// *optional*
+    ///
+    /// default: only the admins can see the metrics
+    pub mail_metrics_token: Option<String>,
 }
 
 impl AppConfig {
@@ -92,6 +159,51 @@ impl AppConfig {
             .get();
 
         let client_url = vars.var_default("APP_CLIENT_URL", app_url.clone()).get();
+        let mail_template_dir = vars.maybe_var("MAIL_TEMPLATE_DIR");
+        let mail_subject_prefix = vars.maybe_var::<String>("MAIL_SUBJECT_PREFIX");
+        let mail_transfer_encoding = vars.var_enum(
+            "MAIL_TRANSFER_ENCODING",
+            TransferEncoding::VALUES,
+            |value| TransferEncoding::from_str(value).map(Some),
+            None,
+        );
+        let mail_default_locale = vars
+            .maybe_var::<String>("MAIL_DEFAULT_LOCALE")
+            .maybe_get()
+            .filter(|v| !v.trim().is_empty());
+        let mail_default_locale = match mail_default_locale {
+            Some(raw) => {
+                let locale = parse_locale(&raw);
+                if locale.is_none() {
+                    vars.add_warning(format!(
+                        "Invalid MAIL_DEFAULT_LOCALE '{raw}', it must be a language tag like 'de' \
+                        or 'pt-BR'. Using the built in English templates"
+                    ));
+                }
+                locale
+            }
+            None => None,
+        };
+        let mail_default_utc_offset = vars
+            .maybe_var::<String>("MAIL_DEFAULT_UTC_OFFSET")
+            .maybe_get()
+            .filter(|v| !v.trim().is_empty());
+        let mail_default_utc_offset = match mail_default_utc_offset {
+            Some(raw) => {
+                let offset = parse_utc_offset(&raw);
+                if offset.is_none() {
+                    vars.add_warning(format!(
+                        "Invalid MAIL_DEFAULT_UTC_OFFSET '{raw}', it must be 'UTC' or an offset \
+                        like '+02:00'. Using UTC"
+                    ));
+                }
+                offset
+            }
+            None => None,
+        };
+        let test_email_cooldown_seconds = vars.var_default("TEST_EMAIL_COOLDOWN_SECONDS", 30);
+        let mail_shutdown_grace_seconds = vars.var_default("MAIL_SHUTDOWN_GRACE_SECONDS", 30);
+        let mail_metrics_token = vars.maybe_var::<String>("MAIL_METRICS_TOKEN");
 
         vars.panic_if_errors("AppConfig");
 
@@ -104,6 +216,20 @@ impl AppConfig {
             version,
             app_url,
             client_url,
+            mail_template_dir: mail_template_dir.maybe_get(),
+            mail_subject_prefix: mail_subject_prefix
+                .maybe_get()
+                .map(|prefix| prefix.trim().to_string())
+                .filter(|prefix| !prefix.is_empty()),
+            mail_transfer_encoding,
+            mail_default_locale,
+            mail_default_utc_offset,
+            test_email_cooldown_seconds: test_email_cooldown_seconds.get(),
+            mail_shutdown_grace_seconds: mail_shutdown_grace_seconds.get(),
+            mail_metrics_token: mail_metrics_token
+                .maybe_get()
+                .map(|token| token.trim().to_string())
+                .filter(|token| !token.is_empty()),
         }
         .set_env()
     }
@@ -0,0 +1,8 @@
+//! Subcommands of the application, without a subcommand the server is started.
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Send the test email from the admin settings to the given address and exit,
+    /// this verifies the email configuration without starting the server.
+    SendTestEmail { to: String },
+}
@@ -29,6 +29,10 @@ pub struct Config {
     /// see more details in the [crate::email::EmailConfig] struct.
     pub mailer: crate::email::EmailConfig,
 
+    /// Subcommand the application was started with, the server is started when not set
+    /// see more details in the [crate::command::Command] enum.
+    pub command: Option<crate::command::Command>,
+
     /// Warnings collected during configuration initialization
     pub(crate) warnings: Vec<String>,
 }
@@ -42,7 +46,7 @@ impl From<Vars> for Config {
         let app = AppConfig::new(&mut vars);
         let ssl = SslConfig::new(&app, &mut vars);
 
-        let mailer = EmailConfig::new(&mut vars);
+        let mailer = EmailConfig::new(&app, &mut vars);
         let auth = crate::auth::AuthConfig::new(&app, &mut vars);
 
         vars.panic_if_errors("Config");
@@ -55,6 +59,7 @@ impl From<Vars> for Config {
             app,
             auth,
             mailer,
+            command: vars.command(),
             warnings,
         }
     }
@@ -1,5 +1,6 @@
 pub mod app;
 pub mod auth;
+pub mod command;
 pub mod config;
 pub mod email;
 pub(crate) mod helpers;
@@ -19,6 +20,38 @@ impl Config {
         self.app.version.clone()
     }
 
+    pub fn get_mail_template_dir(&self) -> Option<String> {
+        self.app.mail_template_dir.clone()
+    }
+
+    pub fn get_mail_subject_prefix(&self) -> Option<String> {
+        self.app.mail_subject_prefix.clone()
+    }
+
+    pub fn get_mail_transfer_encoding(&self) -> Option<email::TransferEncoding> {
+        self.app.mail_transfer_encoding
+    }
+
+    pub fn get_mail_default_locale(&self) -> Option<String> {
+        self.app.mail_default_locale.clone()
+    }
+
+    pub fn get_mail_default_utc_offset(&self) -> Option<chrono::FixedOffset> {
+        self.app.mail_default_utc_offset
+    }
+
+    pub fn get_test_email_cooldown(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.app.test_email_cooldown_seconds)
+    }
+
+    pub fn get_mail_shutdown_grace(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.app.mail_shutdown_grace_seconds)
+    }
+
+    pub fn get_mail_metrics_token(&self) -> Option<&str> {
+        self.app.mail_metrics_token.as_deref()
+    }
+
     pub fn get_app_url(&self) -> String {
         remove_trailing_slash(self.app.app_url.to_string())
     }
@@ -52,6 +85,11 @@ impl Config {
         println!("------------------------------------------");
     }
 
+    /// Warnings collected during configuration initialization
+    pub fn get_warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
     /// Emit any warnings collected during configuration initialization.
     /// Call this after env_logger::init() to ensure warnings are visible.
     pub fn emit_warnings(&self) {
@@ -1,5 +1,6 @@
 use std::str::FromStr;
 
+use crate::command::Command as AppCommand;
 use clap::{builder::Str, Arg, ArgMatches, Command};
 use dotenv::{from_path, vars};
 
@@ -133,6 +134,11 @@ impl Vars {
         }
     }
 
+    /// Add an error to the error list, for values that are set but can't be used
+    pub(crate) fn add_error(&mut self, error: String) {
+        self.errors.push(error);
+    }
+
     /// Add a warning to the warning list
     pub(crate) fn add_warning(&mut self, warning: String) {
         self.warnings.push(warning);
@@ -164,22 +170,38 @@ impl Vars {
         Getter::<Var<T>>::infallible(Some(default))
     }
 
-    /// Get the variable and fail if it isn't found anywhere
-    pub(crate) fn var<T: GetterType>(&mut self, name: &str) -> Getter<Var<T>> {
-        let env = self.maybe_env_var(name);
-        let arg = self.get_match(name);
-
-        if arg.is_some() {
-            return Getter::<Var<T>>::infallible(arg);
-        }
+    /// Get the boolean variable, accepting `true/false`, `1/0`, `yes/no` and `on/off` in any case,
+    /// falling back to the default when it isn't set or the value isn't a valid boolean.
+    pub(crate) fn var_bool_default(&mut self, name: &str, default: bool) -> bool {
+        self.var_enum(name, BOOL_VALUES, parse_bool, default)
+    }
 
-        if env.is_some() {
-            return Getter::<Var<T>>::infallible(env.maybe_get());
-        }
+    /// Get the variable that takes one of the valid values, the parser turns the value into `T`.
+    /// The default is used when the variable isn't set, or when the parser rejects the value,
+    /// which is reported with a warning listing the valid values.
+    pub(crate) fn var_enum<T>(
+        &mut self,
+        name: &str,
+        valid: &[&str],
+        parser: impl Fn(&str) -> Option<T>,
+        default: T,
+    ) -> T {
+        let value = match self.maybe_var::<String>(name).maybe_get() {
+            Some(value) => value,
+            None => return default,
+        };
 
-        self.errors.push(format!("{name} is not set"));
+        match parser(&value) {
+            Some(parsed) => parsed,
+            None => {
+                self.add_warning(format!(
+                    "Invalid {name} '{value}'. Valid values are: {}. Using the default",
+                    valid.join(", ")
+                ));
 
-        Getter::<Var<T>>::infallible(None)
+                default
+            }
+        }
     }
 
     /// Get the variable if it is set in the env or in the command line arguments
@@ -302,9 +324,24 @@ impl Vars {
         }
     }
 
+    /// Subcommand given on the command line, if any
+    pub(crate) fn command(&self) -> Option<AppCommand> {
+        match self.matches.as_ref()?.subcommand()? {
+            ("send-test-email", matches) => Some(AppCommand::SendTestEmail {
+                to: matches.get_one::<String>("to")?.clone(),
+            }),
+            _ => None,
+        }
+    }
+
     /// Define matches from the command line arguments
     pub(crate) fn arguments(&mut self) {
-        let command = Command::new(self.name.clone())
+        self.matches = Some(self.cli().get_matches());
+    }
+
+    /// Command line arguments and subcommands of the application
+    fn cli(&self) -> Command {
+        Command::new(self.name.clone())
         .version(Str::from(self.version.clone()))
         .about(self.about.clone())
         .arg(
@@ -345,9 +382,17 @@ impl Vars {
                 .long("log")
                 .help("Set the RUST_LOG variable")
                 .required(false),
-        );
-
-        self.matches = Some(command.get_matches());
+        )
+        .subcommand(
+            Command::new("send-test-email")
+                .about("Send the test email with the configured mailer and exit, without starting the server")
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .help("Email address the test email is sent to")
+                        .required(true),
+                ),
+        )
     }
 
     #[cfg(test)]
@@ -356,6 +401,18 @@ impl Vars {
     }
 }
 
+/// Values accepted for the boolean variables
+const BOOL_VALUES: &[&str] = &["true", "false", "1", "0", "yes", "no", "on", "off"];
+
+/// Forgiving boolean, people write `1`, `yes` or `on` as often as `true`
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Some(true),
+        "false" | "0" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -379,6 +436,98 @@ mod test {
         getter.get(); // Panics
     }
 
+    #[test]
+    fn test_send_test_email_command() {
+        let mut vars = Vars::create("Hoodik", "v0.1.0", "Hoodik");
+
+        vars.matches = Some(vars.cli().get_matches_from(["hoodik"]));
+        assert_eq!(vars.command(), None);
+
+        vars.matches = Some(vars.cli().get_matches_from([
+            "hoodik",
+            "send-test-email",
+            "--to",
+            "john@doe.com",
+        ]));
+        assert_eq!(
+            vars.command(),
+            Some(AppCommand::SendTestEmail {
+                to: "john@doe.com".to_string()
+            })
+        );
+
+        assert!(vars
+            .cli()
+            .try_get_matches_from(["hoodik", "send-test-email"])
+            .is_err());
+    }
+
+    #[test]
+    fn test_parse_bool() {
+        for value in ["true", "TRUE", "1", "yes", "Yes", "on", " ON "] {
+            assert_eq!(parse_bool(value), Some(true), "{value}");
+        }
+
+        for value in ["false", "False", "0", "no", "NO", "off", "Off"] {
+            assert_eq!(parse_bool(value), Some(false), "{value}");
+        }
+
+        for value in ["", "2", "y", "enabled", "truee"] {
+            assert_eq!(parse_bool(value), None, "{value}");
+        }
+    }
+
+    #[test]
+    fn test_var_bool_default() {
+        let mut vars = Vars::create("test", "0.1.0", "test");
+
+        std::env::set_var("BOOL__TEST_1", "yes");
+        assert!(vars.var_bool_default("BOOL__TEST_1", false));
+
+        std::env::set_var("BOOL__TEST_2", "Off");
+        assert!(!vars.var_bool_default("BOOL__TEST_2", true));
+
+        assert!(vars.var_bool_default("BOOL__TEST_MISSING", true));
+        assert!(vars.get_warnings().is_empty());
+
+        std::env::set_var("BOOL__TEST_3", "maybe");
+        assert!(vars.var_bool_default("BOOL__TEST_3", true));
+        assert_eq!(vars.get_warnings().len(), 1);
+        assert!(vars.get_warnings()[0].contains("Using the default"));
+    }
+
+    #[test]
+    fn test_var_enum() {
+        let mut vars = Vars::create("test", "0.1.0", "test");
+        let parser = |value: &str| match value {
+            "first" => Some(1),
+            "second" => Some(2),
+            _ => None,
+        };
+
+        std::env::set_var("ENUM__TEST_1", "second");
+        assert_eq!(
+            vars.var_enum("ENUM__TEST_1", &["first", "second"], parser, 1),
+            2
+        );
+
+        assert_eq!(
+            vars.var_enum("ENUM__TEST_MISSING", &["first", "second"], parser, 1),
+            1
+        );
+        assert!(vars.get_warnings().is_empty());
+
+        std::env::set_var("ENUM__TEST_2", "third");
+        assert_eq!(
+            vars.var_enum("ENUM__TEST_2", &["first", "second"], parser, 1),
+            1
+        );
+        assert_eq!(
+            vars.get_warnings(),
+            ["Invalid ENUM__TEST_2 'third'. Valid values are: first, second. Using the default"]
+        );
+    }
+
     #[test]
     fn test_vars_env() {
         let mut vars = Vars::create("test", "0.1.0", "test");
@@ -0,0 +1,73 @@
+//! Cooldown between repeated actions of the same user, e.g. sending test emails.
+//! The state is kept in memory and shared between all the clones of the context.
+
+use entity::Uuid;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Default)]
+pub struct Cooldown {
+    last: Arc<Mutex<HashMap<Uuid, Instant>>>,
+}
+
+impl Cooldown {
+    /// Record the action for the key if its cooldown has passed, otherwise
+    /// return how long the key has to wait before it can do it again.
+    ///
+    /// Zero cooldown means there is no limit.
+    pub fn try_acquire(&self, key: Uuid, cooldown: Duration) -> Result<(), Duration> {
+        if cooldown.is_zero() {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+
+        // A poisoned lock only means a panic happened while holding it, the map is still usable
+        let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+
+        last.retain(|_, at| now.duration_since(*at) < cooldown);
+
+        if let Some(at) = last.get(&key) {
+            return Err(cooldown - now.duration_since(*at));
+        }
+
+        last.insert(key, now);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Cooldown;
+    use entity::Uuid;
+    use std::time::Duration;
+
+    #[test]
+    fn repeated_action_waits_for_cooldown() {
+        let cooldown = Cooldown::default();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        let duration = Duration::from_secs(30);
+
+        assert!(cooldown.try_acquire(first, duration).is_ok());
+        assert!(cooldown.try_acquire(second, duration).is_ok());
+
+        let remaining = cooldown.try_acquire(first, duration).unwrap_err();
+
+        assert!(remaining <= duration);
+        assert!(remaining > Duration::from_secs(29));
+
+        // Clones share the state
+        assert!(cooldown.clone().try_acquire(second, duration).is_err());
+    }
+
+    #[test]
+    fn zero_cooldown_is_unlimited() {
+        let cooldown = Cooldown::default();
+        let key = Uuid::new_v4();
+
+        assert!(cooldown.try_acquire(key, Duration::ZERO).is_ok());
+        assert!(cooldown.try_acquire(key, Duration::ZERO).is_ok());
+    }
+}
@@ -1,12 +1,19 @@
 use config::Config;
 use email::Sender;
+use entity::Uuid;
 use error::AppResult;
 use sea_orm::Database;
 
+pub mod cooldown;
+pub mod outbox;
+pub mod preferences;
+
 /// Re-export the database connection type
 pub use sea_orm::DatabaseConnection;
 
-pub use email::contract::SenderContract;
+pub use email::contract::{SendOutcome, SenderCapabilities, SenderContract};
+pub use email::metrics::{EmailMetrics, EmailMetricsSnapshot};
+pub use email::template::{Personalization, Template};
 use settings::{factory::Factory, Settings};
 
 /// Holder of the application context
@@ -18,6 +25,8 @@ pub struct Context {
     pub db: DatabaseConnection,
     pub sender: Option<Sender>,
     pub settings: Settings,
+    /// Cooldown of the admin test emails, keyed by the admin user id
+    pub test_email_cooldown: cooldown::Cooldown,
 }
 
 /// We need to implement clone for the context manually because
@@ -45,6 +54,7 @@ impl Clone for Context {
             },
             sender: self.sender.clone(),
             settings: self.settings.clone(),
+            test_email_cooldown: self.test_email_cooldown.clone(),
         }
     }
 }
@@ -67,9 +77,43 @@ impl Context {
             db,
             sender,
             settings,
+            test_email_cooldown: Default::default(),
         })
     }
 
+    /// Send the email right away, notifications to the users that opted
+    /// out of them are skipped the same way the outbox skips them, see [preferences].
+    pub async fn send_email(&self, template: Template) -> AppResult<()> {
+        let sender = match &self.sender {
+            Some(sender) => sender,
+            None => {
+                log::warn!("No sender configured, skipping email sending");
+
+                return Ok(());
+            }
+        };
+
+        let template = preferences::apply(&self.db, template).await?;
+
+        sender.send_one(template).await
+    }
+
+    /// Queue the email into the outbox instead of sending it right away,
+    /// it will be sent by the background worker, see [outbox].
+    pub async fn enqueue_email(&self, template: Template) -> AppResult<Uuid> {
+        outbox::OutboxRepository::new(&self.db)
+            .enqueue(&template)
+            .await
+    }
+
+    /// Replace the sender with the given implementation of the contract,
+    /// e.g. to check what the application sends in the tests.
+    pub fn with_sender(mut self, sender: Box<dyn SenderContract>) -> Self {
+        self.sender = Some(Sender::with_inner(&self.config, sender));
+
+        self
+    }
+
     #[cfg(feature = "mock")]
     pub fn mock_inject(db: DatabaseConnection) -> Context {
         let config = Config::mock_with_env();
@@ -80,6 +124,7 @@ impl Context {
             db,
             sender: None,
             settings,
+            test_email_cooldown: Default::default(),
         }
     }
 
@@ -93,6 +138,7 @@ impl Context {
             db: DatabaseConnection::Disconnected,
             sender: None,
             settings,
+            test_email_cooldown: Default::default(),
         }
     }
 
@@ -115,6 +161,7 @@ impl Context {
             db,
             sender: None,
             settings,
+            test_email_cooldown: Default::default(),
         };
 
         migration::Migrator::up(&context.db, None).await.unwrap();
@@ -140,6 +187,7 @@ impl Context {
             db,
             sender: None,
             settings,
+            test_email_cooldown: Default::default(),
         };
 
         migration::Migrator::up(&context.db, None).await.unwrap();
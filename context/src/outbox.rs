@@ -0,0 +1,392 @@
+//! # Email outbox
+//!
+//! Instead of sending emails inline while handling a request, they can be queued
+//! into the `email_outbox` table with [Context::enqueue_email](crate::Context::enqueue_email)
+//! and a background worker will pick them up and send them. Queued emails survive
+//! a restart of the application and failed attempts are retried with an
+//! exponential backoff until they run out of attempts.
+//!
+//! Every email is claimed before it is sent, so the drains of several instances, or
+//! two drains that overlap, don't send the same email. The claim is a lease, an email
+//! that was being sent while the application went down is picked up again once the
+//! lease runs out, so delivery is at least once.
+//!
+//! On shutdown the worker stops picking up emails and gets a grace period
+//! (`MAIL_SHUTDOWN_GRACE_SECONDS`) to finish the one it is sending, see
+//! [OutboxWorker::shutdown]. An email that doesn't make it stays in the outbox
+//! and is sent again when its lease runs out.
+
+use crate::{preferences, Context};
+use chrono::Utc;
+use email::{
+    contract::SenderContract,
+    template::{Template, TemplatePayload},
+};
+use entity::{
+    email_outbox::{
+        self, ActiveModel, STATUS_FAILED, STATUS_PENDING, STATUS_SENDING, STATUS_SENT,
+        STATUS_SKIPPED,
+    },
+    ActiveValue, ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter, QueryOrder, QuerySelect,
+    Uuid,
+};
+use error::AppResult;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::Duration;
+use tokio::{sync::Notify, task::JoinHandle};
+use tracing::Instrument;
+
+/// How often the worker checks the outbox for emails that are due
+pub const DRAIN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Maximum number of emails sent in a single drain of the outbox
+pub const DRAIN_BATCH_SIZE: u64 = 20;
+
+/// Number of attempts after which the email is marked as failed
+pub const MAX_ATTEMPTS: i32 = 8;
+
+/// Delay before the first retry, it doubles with every failed attempt
+const BASE_BACKOFF_SECONDS: i64 = 30;
+
+/// Longest delay between two attempts
+const MAX_BACKOFF_SECONDS: i64 = 60 * 60;
+
+/// How long a claimed email belongs to the drain sending it, it has to outlast
+/// the send timeout and the retries of a single email
+pub const CLAIM_LEASE_SECONDS: i64 = 10 * 60;
+
+pub struct OutboxRepository<'ctx, T: ConnectionTrait> {
+    connection: &'ctx T,
+}
+
+impl<'ctx, T> OutboxRepository<'ctx, T>
+where
+    T: ConnectionTrait,
+{
+    pub fn new(connection: &'ctx T) -> Self {
+        Self { connection }
+    }
+
+    /// Store the email in the outbox so it is sent on the next drain
+    pub async fn enqueue(&self, template: &Template) -> AppResult<Uuid> {
+        let id = Uuid::new_v4();
+        let now = Utc::now().timestamp();
+
+        let active_model = ActiveModel {
+            id: ActiveValue::Set(id),
+            payload: ActiveValue::Set(serde_json::to_string(&template.payload())?),
+            status: ActiveValue::Set(STATUS_PENDING.to_string()),
+            attempts: ActiveValue::Set(0),
+            last_error: ActiveValue::Set(None),
+            next_attempt_at: ActiveValue::Set(now),
+            created_at: ActiveValue::Set(now),
+            sent_at: ActiveValue::Set(None),
+        };
+
+        email_outbox::Entity::insert(active_model)
+            .exec_without_returning(self.connection)
+            .await?;
+
+        Ok(id)
+    }
+
+    /// Find the pending emails that are due to be sent, oldest first, together with the
+    /// claimed ones whose lease ran out because the drain sending them never finished
+    pub async fn due(&self, limit: u64) -> AppResult<Vec<email_outbox::Model>> {
+        email_outbox::Entity::find()
+            .filter(email_outbox::Column::Status.is_in([STATUS_PENDING, STATUS_SENDING]))
+            .filter(email_outbox::Column::NextAttemptAt.lte(Utc::now().timestamp()))
+            .order_by_asc(email_outbox::Column::NextAttemptAt)
+            .limit(limit)
+            .all(self.connection)
+            .await
+            .map_err(error::Error::from)
+    }
+
+    /// Claim the due email for sending, only one drain can claim it. The update only matches
+    /// while the row is exactly as it was found, `false` means another drain got it first.
+    pub async fn claim(&self, email: &email_outbox::Model) -> AppResult<bool> {
+        let active_model = ActiveModel {
+            status: ActiveValue::Set(STATUS_SENDING.to_string()),
+            next_attempt_at: ActiveValue::Set(Utc::now().timestamp() + CLAIM_LEASE_SECONDS),
+            ..Default::default()
+        };
+
+        let results = email_outbox::Entity::update_many()
+            .filter(email_outbox::Column::Id.eq(email.id))
+            .filter(email_outbox::Column::Status.eq(email.status.as_str()))
+            .filter(email_outbox::Column::NextAttemptAt.eq(email.next_attempt_at))
+            .set(active_model)
+            .exec(self.connection)
+            .await?;
+
+        Ok(results.rows_affected == 1)
+    }
+
+    /// Get a single email from the outbox
+    pub async fn get(&self, id: Uuid) -> AppResult<email_outbox::Model> {
+        email_outbox::Entity::find_by_id(id)
+            .one(self.connection)
+            .await?
+            .ok_or_else(|| error::Error::NotFound("email_not_found".to_string()))
+    }
+
+    /// Mark the email as sent
+    pub async fn mark_sent(&self, email: &email_outbox::Model) -> AppResult<()> {
+        let active_model = ActiveModel {
+            id: ActiveValue::Unchanged(email.id),
+            status: ActiveValue::Set(STATUS_SENT.to_string()),
+            attempts: ActiveValue::Set(email.attempts + 1),
+            sent_at: ActiveValue::Set(Some(Utc::now().timestamp())),
+            ..Default::default()
+        };
+
+        email_outbox::Entity::update(active_model)
+            .exec(self.connection)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Mark the email as skipped, it was intentionally not sent and won't be retried
+    pub async fn mark_skipped(&self, email: &email_outbox::Model) -> AppResult<()> {
+        let active_model = ActiveModel {
+            id: ActiveValue::Unchanged(email.id),
+            status: ActiveValue::Set(STATUS_SKIPPED.to_string()),
+            ..Default::default()
+        };
+
+        email_outbox::Entity::update(active_model)
+            .exec(self.connection)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Record a failed attempt, the email is scheduled for a retry with backoff
+    /// or marked as failed if it ran out of attempts or can never be sent.
+    pub async fn mark_attempt_failed(
+        &self,
+        email: &email_outbox::Model,
+        error: String,
+        retry: bool,
+    ) -> AppResult<()> {
+        let attempts = email.attempts + 1;
+
+        let status = match retry && attempts < MAX_ATTEMPTS {
+            true => STATUS_PENDING,
+            false => STATUS_FAILED,
+        };
+
+        let active_model = ActiveModel {
+            id: ActiveValue::Unchanged(email.id),
+            status: ActiveValue::Set(status.to_string()),
+            attempts: ActiveValue::Set(attempts),
+            last_error: ActiveValue::Set(Some(error)),
+            next_attempt_at: ActiveValue::Set(Utc::now().timestamp() + backoff(attempts)),
+            ..Default::default()
+        };
+
+        email_outbox::Entity::update(active_model)
+            .exec(self.connection)
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Seconds to wait before the next attempt after the given number of failed attempts
+pub fn backoff(attempts: i32) -> i64 {
+    let exponent = attempts.saturating_sub(1).clamp(0, 16) as u32;
+
+    BASE_BACKOFF_SECONDS
+        .saturating_mul(2_i64.pow(exponent))
+        .min(MAX_BACKOFF_SECONDS)
+}
+
+/// Send all the emails from the outbox that are due, returns the number of sent emails.
+/// Nothing is drained when there is no sender, the emails wait until one is configured.
+///
+/// The preferences of the recipients are checked right before sending, notifications
+/// to users that opted out are skipped, see [preferences](crate::preferences).
+pub async fn drain(context: &Context) -> AppResult<usize> {
+    drain_until_stopped(context, &AtomicBool::new(false)).await
+}
+
+/// Drain the outbox, no more emails are picked up once the worker is stopping
+async fn drain_until_stopped(context: &Context, stopping: &AtomicBool) -> AppResult<usize> {
+    let sender = match &context.sender {
+        Some(sender) => sender,
+        None => return Ok(0),
+    };
+
+    let repository = OutboxRepository::new(&context.db);
+    let mut sent = 0;
+
+    for email in repository.due(DRAIN_BATCH_SIZE).await? {
+        if stopping.load(Ordering::SeqCst) {
+            break;
+        }
+
+        if !repository.claim(&email).await? {
+            log::debug!(
+                "Skipping the queued email {}, another drain is sending it",
+                email.id
+            );
+
+            continue;
+        }
+
+        let template = match serde_json::from_str::<TemplatePayload>(&email.payload)
+            .map_err(error::Error::from)
+            .and_then(Template::from_payload)
+        {
+            Ok(template) => preferences::apply(&context.db, template).await?,
+            Err(e) => {
+                log::error!("Failed rebuilding the queued email {}: {:?}", email.id, e);
+
+                repository
+                    .mark_attempt_failed(&email, format!("{e:?}"), false)
+                    .await?;
+
+                continue;
+            }
+        };
+
+        if template.skip_send() {
+            log::debug!(
+                "Skipping the queued email {}, it is not meant to be sent",
+                email.id
+            );
+
+            repository.mark_skipped(&email).await?;
+
+            continue;
+        }
+
+        // Errors that are not classified (e.g. the sender reporting nothing was sent)
+        // are retried, only the ones the server or the client rejected for good are not.
+        let span = tracing::info_span!(
+            "outbox_send",
+            email_id = %email.id,
+            attempt = email.attempts + 1,
+        );
+
+        let result = sender
+            .send_one(template)
+            .instrument(span)
+            .await
+            .map_err(|e| {
+                let retry = e.email_error_kind().is_none_or(|kind| kind.is_retryable());
+
+                // The reply of the relay tells the most about why the email was rejected
+                let error = match e.smtp_reply() {
+                    Some((code, message)) => format!("smtp_relay_rejected: {code} {message}"),
+                    None => format!("{e:?}"),
+                };
+
+                (error, retry)
+            });
+
+        match result {
+            Ok(()) => {
+                repository.mark_sent(&email).await?;
+
+                sent += 1;
+            }
+            Err((e, retry)) => {
+                log::warn!("Failed sending the queued email {}: {}", email.id, e);
+
+                repository.mark_attempt_failed(&email, e, retry).await?;
+            }
+        }
+    }
+
+    Ok(sent)
+}
+
+/// Handle of the background worker, used to stop it when the application shuts down
+pub struct OutboxWorker {
+    stopping: Arc<AtomicBool>,
+    stop: Arc<Notify>,
+    handle: JoinHandle<()>,
+}
+
+impl OutboxWorker {
+    /// Stop picking up emails and wait for the email that is being sent to finish.
+    /// When it doesn't finish within the grace period the worker is cancelled,
+    /// the email stays claimed and it is sent again once the lease runs out.
+    pub async fn shutdown(self, grace: Duration) {
+        self.stopping.store(true, Ordering::SeqCst);
+        self.stop.notify_one();
+
+        let abort = self.handle.abort_handle();
+
+        if tokio::time::timeout(grace, self.handle).await.is_err() {
+            log::warn!(
+                "The email outbox didn't finish sending within {}s, the unsent emails stay queued",
+                grace.as_secs()
+            );
+
+            abort.abort();
+        }
+    }
+}
+
+/// Start the background worker that drains the outbox periodically,
+/// this has to be called from within the actix runtime.
+pub fn spawn_worker(context: Context) -> OutboxWorker {
+    let stopping = Arc::new(AtomicBool::new(false));
+    let stop = Arc::new(Notify::new());
+
+    let handle = actix_web::rt::spawn({
+        let stopping = stopping.clone();
+        let stop = stop.clone();
+
+        async move {
+            let mut interval = actix_web::rt::time::interval(DRAIN_INTERVAL);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => (),
+                    _ = stop.notified() => break,
+                }
+
+                if let Err(e) = drain_until_stopped(&context, &stopping).await {
+                    log::error!("Failed draining the email outbox: {:?}", e);
+                }
+
+                if stopping.load(Ordering::SeqCst) {
+                    break;
+                }
+            }
+        }
+    });
+
+    OutboxWorker {
+        stopping,
+        stop,
+        handle,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{backoff, BASE_BACKOFF_SECONDS, MAX_BACKOFF_SECONDS};
+
+    #[test]
+    fn backoff_doubles_with_every_attempt() {
+        assert_eq!(backoff(1), BASE_BACKOFF_SECONDS);
+        assert_eq!(backoff(2), BASE_BACKOFF_SECONDS * 2);
+        assert_eq!(backoff(3), BASE_BACKOFF_SECONDS * 4);
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        assert_eq!(backoff(20), MAX_BACKOFF_SECONDS);
+        assert_eq!(backoff(i32::MAX), MAX_BACKOFF_SECONDS);
+    }
+}
@@ -0,0 +1,58 @@
+//! Email preferences of the users, the notification emails are not sent
+//! to the users that turned them off, transactional emails are always sent.
+
+use email::template::Template;
+use entity::{users, ColumnTrait, ConnectionTrait, EntityTrait, Expr, QueryFilter};
+use error::AppResult;
+use sea_orm::sea_query::Func;
+use std::collections::HashSet;
+
+/// Mark the notification email to not be sent when its recipients opted out,
+/// the email is skipped just like any other email that shouldn't be sent.
+pub async fn apply<T: ConnectionTrait>(connection: &T, template: Template) -> AppResult<Template> {
+    if !template.is_notification() {
+        return Ok(template);
+    }
+
+    match opted_out(connection, &template.recipients()).await? {
+        true => Ok(template.opt_out()),
+        false => Ok(template),
+    }
+}
+
+/// Did all the recipients turn off the notification emails, a single message can't
+/// be sent to only some of its recipients, so it is sent if anyone still wants it.
+/// Recipients that are not users have no preference and always get the emails.
+///
+/// The recipients are bare email addresses, they are matched against the
+/// emails of the users regardless of the case either of them is stored in.
+pub async fn opted_out<T: ConnectionTrait>(
+    connection: &T,
+    recipients: &[String],
+) -> AppResult<bool> {
+    let recipients = recipients
+        .iter()
+        .map(|email| email.to_lowercase())
+        .collect::<HashSet<_>>();
+
+    if recipients.is_empty() {
+        return Ok(false);
+    }
+
+    let opted_out = users::Entity::find()
+        .filter(
+            Expr::expr(Func::lower(Expr::col((
+                users::Entity,
+                users::Column::Email,
+            ))))
+            .is_in(recipients.iter().cloned()),
+        )
+        .filter(users::Column::EmailNotificationsDisabledAt.is_not_null())
+        .all(connection)
+        .await?
+        .into_iter()
+        .map(|user| user.email.to_lowercase())
+        .collect::<HashSet<_>>();
+
+    Ok(recipients.is_subset(&opted_out))
+}
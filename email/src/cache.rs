@@ -0,0 +1,68 @@
+//! Cache of the parsed handlebars templates so the same content isn't parsed again
+//! for every email, e.g. when the same notification is sent to many recipients.
+
+use error::AppResult;
+use handlebars::Template as Compiled;
+use std::collections::HashMap;
+use std::sync::{OnceLock, RwLock};
+
+#[cfg(test)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Maximum number of cached templates, the cache is cleared when it fills up,
+/// templates come from the code or the template directory so this is rarely reached.
+const MAX_CACHED_TEMPLATES: usize = 256;
+
+#[derive(Default)]
+pub(crate) struct TemplateCache {
+    templates: RwLock<HashMap<(String, String), Compiled>>,
+    #[cfg(test)]
+    compiled: AtomicUsize,
+}
+
+impl TemplateCache {
+    /// Cache shared by all the templates and senders
+    pub(crate) fn global() -> &'static TemplateCache {
+        static CACHE: OnceLock<TemplateCache> = OnceLock::new();
+
+        CACHE.get_or_init(TemplateCache::default)
+    }
+
+    /// Get the parsed template for the name and content, parsing it only if it isn't cached yet
+    pub(crate) fn get(&self, name: &str, content: &str) -> AppResult<Compiled> {
+        let key = (name.to_string(), content.to_string());
+
+        // A poisoned lock only means a panic happened while holding it, the map is still usable
+        let cached = self
+            .templates
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&key)
+            .cloned();
+
+        if let Some(compiled) = cached {
+            return Ok(compiled);
+        }
+
+        let compiled = Compiled::compile_with_name(content, name.to_string())?;
+
+        #[cfg(test)]
+        self.compiled.fetch_add(1, Ordering::SeqCst);
+
+        let mut templates = self.templates.write().unwrap_or_else(|e| e.into_inner());
+
+        if templates.len() >= MAX_CACHED_TEMPLATES {
+            templates.clear();
+        }
+
+        templates.insert(key, compiled.clone());
+
+        Ok(compiled)
+    }
+
+    /// Number of times a template had to be parsed
+    #[cfg(test)]
+    pub(crate) fn compiled(&self) -> usize {
+        self.compiled.load(Ordering::SeqCst)
+    }
+}
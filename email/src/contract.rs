@@ -1,5 +1,91 @@
-use crate::template::Template;
-use error::AppResult;
+use crate::template::{Personalization, Template};
+use error::{AppResult, Error};
+use serde::Serialize;
+
+/// What the sender is able to do with the emails, so the callers and the admin UI
+/// can tell it without knowing which sender is configured
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SenderCapabilities {
+    /// Attachments are delivered along with the HTML, e.g. the calendar invites
+    pub supports_attachments: bool,
+    /// Hidden recipients are delivered without the other recipients seeing them
+    pub supports_bcc: bool,
+    /// The delivery backend can be reached with `test_connection` without sending anything
+    pub supports_connection_test: bool,
+    /// The emails actually leave the server instead of being logged or recorded
+    pub is_real_delivery: bool,
+}
+
+/// Result of sending a single email (one message, no matter the number of recipients)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SendOutcome {
+    /// Addresses of all the recipients of the email, including the hidden ones
+    pub recipients: Vec<String>,
+    /// Was the email accepted for delivery
+    pub sent: bool,
+    /// Identifier of the email, the queue id reported by the relay when it has one,
+    /// otherwise the Message-ID header of the email
+    pub message_id: Option<String>,
+    /// Why the email wasn't sent
+    pub error: Option<String>,
+    /// Reply of the relay to the email, accepted or not, when the sender talks to one
+    pub relay_response: Option<RelayResponse>,
+}
+
+/// Status code and message the relay replied with, e.g. `550 5.1.1 Mailbox unavailable`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayResponse {
+    pub code: u16,
+    pub message: String,
+}
+
+impl RelayResponse {
+    pub fn new(code: u16, message: String) -> Self {
+        Self { code, message }
+    }
+}
+
+impl std::fmt::Display for RelayResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.message.is_empty() {
+            true => write!(f, "{}", self.code),
+            false => write!(f, "{} {}", self.code, self.message),
+        }
+    }
+}
+
+impl SendOutcome {
+    pub fn sent(recipients: Vec<String>, message_id: Option<String>) -> Self {
+        Self {
+            recipients,
+            sent: true,
+            message_id,
+            error: None,
+            relay_response: None,
+        }
+    }
+
+    pub fn failed(recipients: Vec<String>, error: String) -> Self {
+        Self {
+            recipients,
+            sent: false,
+            message_id: None,
+            error: Some(error),
+            relay_response: None,
+        }
+    }
+
+    /// Attach the reply of the relay to the outcome
+    pub fn with_relay_response(mut self, relay_response: Option<RelayResponse>) -> Self {
+        self.relay_response = relay_response;
+        self
+    }
+
+    /// Number of sent emails, the same number `SenderContract::send` returns
+    pub fn count_sent(outcomes: &[SendOutcome]) -> usize {
+        outcomes.iter().filter(|outcome| outcome.sent).count()
+    }
+}
 
 /// Sender contract that will be setup on the
 /// context in order to enable sending emails
@@ -8,9 +94,94 @@ pub trait SenderContract
 where
     Self: Send + Sync,
 {
-    /// Send generated emails
+    /// Send generated emails, returns the number of sent messages,
+    /// a template with multiple recipients is a single message.
     async fn send(&self, emails: Vec<Template>) -> AppResult<usize>;
 
+    /// Send generated emails and report the outcome of each one of them, unlike `send`
+    /// a failed email doesn't fail the whole batch, the error is in its outcome.
+    ///
+    /// By default the emails are sent one by one without any identifiers,
+    /// senders that know more about the delivery should override this.
+    async fn send_outcomes(&self, emails: Vec<Template>) -> AppResult<Vec<SendOutcome>> {
+        let mut outcomes = vec![];
+
+        for email in emails {
+            let recipients = email.recipients();
+
+            let outcome = match self.send(vec![email]).await {
+                Ok(0) => SendOutcome::failed(recipients, "email_not_sent".to_string()),
+                Ok(_) => SendOutcome::sent(recipients, None),
+                Err(e) => SendOutcome::failed(recipients, e.to_string()),
+            };
+
+            outcomes.push(outcome);
+        }
+
+        Ok(outcomes)
+    }
+
+    /// Send the template to every recipient with the variables of the recipient, the outcomes
+    /// are per recipient and in the same order as the recipients. A recipient with an invalid
+    /// address fails only its own outcome.
+    async fn send_personalized(
+        &self,
+        template: Template,
+        recipients: &[Personalization],
+    ) -> AppResult<Vec<SendOutcome>> {
+        let mut emails = vec![];
+        let mut errors = vec![];
+
+        for recipient in recipients {
+            match template.personalized(recipient) {
+                Ok(email) => {
+                    emails.push(email);
+                    errors.push(None);
+                }
+                Err(e) => errors.push(Some(e.to_string())),
+            }
+        }
+
+        let mut sent = self.send_outcomes(emails).await?.into_iter();
+
+        recipients
+            .iter()
+            .zip(errors)
+            .map(|(recipient, error)| match error {
+                Some(error) => Ok(SendOutcome::failed(vec![recipient.to.clone()], error)),
+                None => sent.next().ok_or_else(|| {
+                    Error::InternalError("missing_outcome_of_personalized_email".to_string())
+                }),
+            })
+            .collect()
+    }
+
+    /// Send the emails with the notifications to the same recipient coalesced into a single
+    /// digest, transactional emails are sent as they are, see [coalesce](crate::digest::coalesce).
+    /// Returns the number of sent messages, a digest is one message.
+    async fn send_digest(&self, emails: Vec<Template>, subject: &str) -> AppResult<usize> {
+        let emails = crate::digest::coalesce(emails, subject)?;
+
+        self.send(emails).await
+    }
+
+    /// Send a single email and fail if it wasn't sent
+    async fn send_one(&self, email: Template) -> AppResult<()> {
+        match self.send(vec![email]).await? {
+            0 => Err(Error::InternalError("email_not_sent".to_string())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that the sender is able to reach its delivery backend without
+    /// sending anything, senders that can't be probed will always succeed.
+    async fn test_connection(&self) -> AppResult<()> {
+        Ok(())
+    }
+
+    /// What the sender supports, every sender tells it explicitly
+    fn capabilities(&self) -> SenderCapabilities;
+
     /// Create a new email template with the base HTML
     fn template(&self, subject: &str, pre_header: &str) -> AppResult<Template> {
         Template::new(subject, pre_header)
@@ -32,3 +203,98 @@ where
         None
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{SendOutcome, SenderCapabilities, SenderContract};
+    use crate::template::{Personalization, Template};
+    use error::AppResult;
+
+    #[derive(Clone)]
+    struct CountingSender(usize);
+
+    #[async_trait::async_trait]
+    impl SenderContract for CountingSender {
+        async fn send(&self, _emails: Vec<Template>) -> AppResult<usize> {
+            Ok(self.0)
+        }
+
+        fn capabilities(&self) -> SenderCapabilities {
+            SenderCapabilities {
+                supports_attachments: false,
+                supports_bcc: false,
+                supports_connection_test: false,
+                is_real_delivery: false,
+            }
+        }
+
+        fn boxed_clone(&self) -> Box<dyn SenderContract> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn send_one_succeeds_when_email_was_sent() {
+        let template = Template::new("subject", "pre_header").unwrap();
+
+        assert!(CountingSender(1).send_one(template).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn send_one_fails_when_nothing_was_sent() {
+        let template = Template::new("subject", "pre_header").unwrap();
+
+        assert!(CountingSender(0).send_one(template).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn send_outcomes_reports_each_email() {
+        let template = Template::new("subject", "pre_header")
+            .unwrap()
+            .to("first@doe.com")
+            .unwrap();
+
+        let outcomes = CountingSender(1)
+            .send_outcomes(vec![template.clone(), template.clone()])
+            .await
+            .unwrap();
+
+        assert_eq!(SendOutcome::count_sent(&outcomes), 2);
+        assert_eq!(outcomes[0].recipients, vec!["first@doe.com"]);
+
+        let outcomes = CountingSender(0)
+            .send_outcomes(vec![template])
+            .await
+            .unwrap();
+
+        assert_eq!(SendOutcome::count_sent(&outcomes), 0);
+        assert_eq!(outcomes[0].error.as_deref(), Some("email_not_sent"));
+    }
+
+    #[tokio::test]
+    async fn send_personalized_reports_each_recipient() {
+        let template = Template::new("subject", "pre_header").unwrap();
+
+        let recipients = vec![
+            Personalization::new("first@doe.com").var("name", "First"),
+            Personalization::new("not an address"),
+            Personalization::new("second@doe.com").var("name", "Second"),
+        ];
+
+        let outcomes = CountingSender(1)
+            .send_personalized(template, &recipients)
+            .await
+            .unwrap();
+
+        assert_eq!(outcomes.len(), 3);
+        assert_eq!(SendOutcome::count_sent(&outcomes), 2);
+        assert_eq!(outcomes[0].recipients, vec!["first@doe.com"]);
+        assert_eq!(outcomes[1].recipients, vec!["not an address"]);
+        assert!(outcomes[1]
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("invalid_to_address_provided"));
+        assert_eq!(outcomes[2].recipients, vec!["second@doe.com"]);
+    }
+}
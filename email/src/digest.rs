@@ -0,0 +1,192 @@
+//! Notifications sent to the same recipient in a short window can be coalesced into
+//! a single digest email, so a busy instance doesn't flood the inbox with them.
+
+use crate::template::{EmailCategory, Template};
+use error::AppResult;
+use handlebars::html_escape;
+
+/// Variables of the layout the digest takes over from the coalesced emails
+const LAYOUT_VARS: [&str; 2] = ["base_app_name", "base_app_version"];
+
+/// Coalesce the notifications addressed to the same single recipient into one digest with
+/// the given subject, every notification becomes a section under its own subject as the heading.
+///
+/// Transactional emails, emails with several recipients and the ones that are not sent
+/// are never merged, they are returned as they are. The emails keep the order they came in,
+/// a digest takes the place of its first notification.
+pub fn coalesce(emails: Vec<Template>, subject: &str) -> AppResult<Vec<Template>> {
+    let mut groups: Vec<(Option<String>, Vec<Template>)> = vec![];
+
+    for email in emails {
+        let recipient = digest_recipient(&email);
+
+        let group = recipient.as_ref().and_then(|recipient| {
+            groups
+                .iter_mut()
+                .find(|(key, _)| key.as_ref() == Some(recipient))
+        });
+
+        match group {
+            Some((_, group)) => group.push(email),
+            None => groups.push((recipient, vec![email])),
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|(_, mut group)| match group.len() {
+            1 => Ok(group.remove(0)),
+            _ => digest(subject, group),
+        })
+        .collect()
+}
+
+/// Recipient the email can be coalesced for, only notifications to a single recipient have one
+fn digest_recipient(email: &Template) -> Option<String> {
+    if !email.is_notification() || email.skip_send() || !email.has_to() {
+        return None;
+    }
+
+    match email.recipients().as_slice() {
+        [recipient] => Some(recipient.to_lowercase()),
+        _ => None,
+    }
+}
+
+/// Build the digest out of the notifications, the sender, the recipient and the unsubscribe
+/// link are taken from the first one, they are the same for the notifications of a recipient.
+fn digest(subject: &str, emails: Vec<Template>) -> AppResult<Template> {
+    let first = emails[0].payload();
+
+    let subjects = emails
+        .iter()
+        .map(|email| email.payload().subject)
+        .collect::<Vec<String>>();
+
+    let mut digest =
+        Template::new(subject, &subjects.join(", "))?.category(EmailCategory::Notification);
+
+    for var in LAYOUT_VARS {
+        if let Some(value) = first.data.get(var) {
+            digest.data.insert(var.to_string(), value.clone());
+        }
+    }
+
+    let mut sections = vec![];
+
+    for (email, subject) in emails.iter().zip(subjects.iter()) {
+        sections.push(format!(
+            "<h2>{}</h2>\n{}",
+            html_escape(subject),
+            email.render_content()?
+        ));
+    }
+
+    // Sections are rendered already, the values in them were escaped by their own emails
+    digest.register_content_template("{{ digest_sections }}")?;
+    digest.add_raw_template_var("digest_sections", sections.join("\n<hr>\n"));
+
+    if let Some(from) = first.from.as_deref() {
+        digest = digest.from(from)?;
+    }
+
+    if let Some(name) = first.from_display_name.as_deref() {
+        digest = digest.from_display_name(name);
+    }
+
+    if let Some(reply_to) = first.reply_to.as_deref() {
+        digest = digest.reply_to(reply_to)?;
+    }
+
+    for to in first.to.iter() {
+        digest = digest.to(to)?;
+    }
+
+    if let Some(list_unsubscribe) = first.list_unsubscribe.as_deref() {
+        digest.set_list_unsubscribe(list_unsubscribe)?;
+    }
+
+    if let Some(suppress) = first.suppress_auto_replies {
+        digest = digest.suppress_auto_replies(suppress);
+    }
+
+    if let Some(encoding) = first.transfer_encoding {
+        digest.set_transfer_encoding(encoding);
+    }
+
+    if let Some(locale) = first.locale.as_deref() {
+        digest.set_locale(locale)?;
+    }
+
+    if let Some(timezone) = first.timezone.and_then(chrono::FixedOffset::east_opt) {
+        digest.set_timezone(timezone);
+    }
+
+    Ok(digest)
+}
+
+#[cfg(test)]
+mod test {
+    use super::coalesce;
+    use crate::contract::SenderContract;
+    use crate::senders::dryrun::DryRunSender;
+    use crate::template::{EmailCategory, Template};
+
+    fn notification(to: &str, subject: &str, content: &str) -> Template {
+        let mut template = Template::new(subject, "pre_header")
+            .unwrap()
+            .category(EmailCategory::Notification);
+
+        template
+            .register_content_template("<p>{{ content }}</p>")
+            .unwrap();
+        template.add_template_var("content", content);
+
+        template.to(to).unwrap()
+    }
+
+    #[test]
+    fn notifications_to_the_same_recipient_are_coalesced() {
+        let transactional = Template::new("Reset password", "pre_header")
+            .unwrap()
+            .to("john@doe.com")
+            .unwrap();
+
+        let emails = vec![
+            notification("john@doe.com", "File shared", "<b>report.pdf</b>"),
+            transactional,
+            notification("jane@doe.com", "Link expired", "link"),
+            notification("John@doe.com", "File deleted", "notes.txt"),
+        ];
+
+        let coalesced = coalesce(emails, "Your notifications").unwrap();
+
+        assert_eq!(coalesced.len(), 3);
+
+        let digest = &coalesced[0];
+        let html = digest.render_html().unwrap();
+
+        assert_eq!(digest.payload().subject, "Your notifications");
+        assert_eq!(digest.payload().pre_header, "File shared, File deleted");
+        assert!(digest.is_notification());
+        assert!(html.contains("<h2>File shared</h2>\n<p>&lt;b&gt;report.pdf&lt;/b&gt;</p>"));
+        assert!(html.contains("<h2>File deleted</h2>\n<p>notes.txt</p>"));
+
+        // Transactional emails and the single notifications are left as they are
+        assert_eq!(coalesced[1].payload().subject, "Reset password");
+        assert_eq!(coalesced[2].payload().subject, "Link expired");
+    }
+
+    #[tokio::test]
+    async fn send_digest_counts_the_coalesced_messages() {
+        let sender = DryRunSender::new("Hoodik").unwrap();
+
+        let emails = vec![
+            notification("john@doe.com", "First", "first"),
+            notification("john@doe.com", "Second", "second"),
+            notification("jane@doe.com", "Third", "third"),
+        ];
+
+        assert_eq!(sender.send_digest(emails, "Digest").await.unwrap(), 2);
+    }
+}
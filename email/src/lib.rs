@@ -1,20 +1,32 @@
-use config::email::EmailConfig;
+use chrono::FixedOffset;
+use config::email::{EmailConfig, TransferEncoding};
 use error::AppResult;
-use senders::smtp::SmtpSender;
+use senders::{dryrun::DryRunSender, smtp::SmtpSender, webhook::WebhookSender};
 
 #[cfg(feature = "mock")]
 use crate::senders::mock::MockSender;
 
+mod cache;
 pub mod contract;
+pub mod digest;
+pub mod metrics;
 pub mod senders;
 pub mod template;
 
+#[cfg(any(test, feature = "mock"))]
+pub mod test_server;
+
 /// Email sender that can be instantiated by using the application
 /// config, it will automatically extract all the needed parts
 /// to set the inner into proper sending mode.
 pub struct Sender {
     app_name: String,
     app_version: String,
+    template_dir: Option<std::path::PathBuf>,
+    subject_prefix: Option<String>,
+    transfer_encoding: Option<TransferEncoding>,
+    locale: Option<String>,
+    timezone: Option<FixedOffset>,
     inner: Box<dyn contract::SenderContract>,
 }
 
@@ -23,6 +35,11 @@ impl Clone for Sender {
         Self {
             app_name: self.app_name.clone(),
             app_version: self.app_version.clone(),
+            template_dir: self.template_dir.clone(),
+            subject_prefix: self.subject_prefix.clone(),
+            transfer_encoding: self.transfer_encoding,
+            locale: self.locale.clone(),
+            timezone: self.timezone,
             inner: self.inner.boxed_clone(),
         }
     }
@@ -32,29 +49,72 @@ impl Sender {
     pub fn new(config: &config::Config) -> AppResult<Option<Self>> {
         let app_name = config.get_app_name();
         let app_version = config.get_app_version();
+        let template_dir = config.get_mail_template_dir().map(std::path::PathBuf::from);
+        let subject_prefix = config.get_mail_subject_prefix();
+        let transfer_encoding = config.get_mail_transfer_encoding();
+        let locale = config.get_mail_default_locale();
+        let timezone = config.get_mail_default_utc_offset();
 
         Ok(match &config.mailer {
             EmailConfig::Smtp(c) => Some(Self {
                 app_name,
                 app_version,
-                inner: Box::new(SmtpSender::new(
-                    &c.address,
-                    &c.username,
-                    &c.password,
-                    c.port,
-                    &c.tls_mode,
-                    &c.default_from,
-                )?),
+                template_dir,
+                subject_prefix,
+                transfer_encoding,
+                locale,
+                timezone,
+                inner: Box::new(SmtpSender::new(c)?),
+            }),
+            EmailConfig::Webhook(c) => Some(Self {
+                app_name,
+                app_version,
+                template_dir,
+                subject_prefix,
+                transfer_encoding,
+                locale,
+                timezone,
+                inner: Box::new(WebhookSender::new(c)?),
+            }),
+            EmailConfig::DryRun => Some(Self {
+                inner: Box::new(DryRunSender::new(&app_name)?),
+                app_name,
+                app_version,
+                template_dir,
+                subject_prefix,
+                transfer_encoding,
+                locale,
+                timezone,
             }),
             EmailConfig::None => None,
         })
     }
 
+    /// Create the sender around any implementation of the contract, e.g. a test double,
+    /// the templates still get the application name and version from the config.
+    pub fn with_inner(config: &config::Config, inner: Box<dyn contract::SenderContract>) -> Self {
+        Self {
+            app_name: config.get_app_name(),
+            app_version: config.get_app_version(),
+            template_dir: config.get_mail_template_dir().map(std::path::PathBuf::from),
+            subject_prefix: config.get_mail_subject_prefix(),
+            transfer_encoding: config.get_mail_transfer_encoding(),
+            locale: config.get_mail_default_locale(),
+            timezone: config.get_mail_default_utc_offset(),
+            inner,
+        }
+    }
+
     #[cfg(feature = "mock")]
     pub fn mock() -> Self {
         Self {
             app_name: "Mock Hoodik".to_string(),
             app_version: "0.1.0".to_string(),
+            template_dir: None,
+            subject_prefix: None,
+            transfer_encoding: None,
+            locale: None,
+            timezone: None,
             inner: Box::new(MockSender::new()),
         }
     }
@@ -66,14 +126,53 @@ impl contract::SenderContract for Sender {
         self.inner.send(emails).await
     }
 
+    async fn send_outcomes(
+        &self,
+        emails: Vec<template::Template>,
+    ) -> error::AppResult<Vec<contract::SendOutcome>> {
+        self.inner.send_outcomes(emails).await
+    }
+
+    async fn send_one(&self, email: template::Template) -> error::AppResult<()> {
+        self.inner.send_one(email).await
+    }
+
+    async fn test_connection(&self) -> error::AppResult<()> {
+        self.inner.test_connection().await
+    }
+
+    fn capabilities(&self) -> contract::SenderCapabilities {
+        self.inner.capabilities()
+    }
+
     /// We will override the default behavior here because we want it to always have
     /// app version and name in production settings, and this will enable that.
+    /// The subject gets the MAIL_SUBJECT_PREFIX, the body the MAIL_TRANSFER_ENCODING and
+    /// the template the MAIL_DEFAULT_LOCALE and MAIL_DEFAULT_UTC_OFFSET when they are configured,
+    /// the template can still be given another locale or timezone.
     fn template(&self, subject: &str, pre_header: &str) -> AppResult<template::Template> {
-        let mut template = template::Template::new(subject, pre_header)?;
+        let subject = template::prefix_subject(self.subject_prefix.as_deref(), subject);
+        let mut template = template::Template::new(&subject, pre_header)?;
 
         template.add_template_var("base_app_name", self.app_name.as_str());
         template.add_template_var("base_app_version", self.app_version.as_str());
 
+        if let Some(dir) = self.template_dir.as_deref() {
+            template.set_template_dir(dir);
+        }
+
+        if let Some(encoding) = self.transfer_encoding {
+            template.set_transfer_encoding(encoding);
+        }
+
+        if let Some(locale) = self.locale.as_deref() {
+            template.set_locale(locale)?;
+        }
+
+        if let Some(timezone) = self.timezone {
+            template.set_timezone(timezone);
+        }
+
         Ok(template)
     }
 
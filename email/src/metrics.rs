@@ -0,0 +1,126 @@
+//! Counters of sent and failed emails, the operators can alert on them
+//! when the delivery starts failing. The counters live in memory and are
+//! created on first use, they are reset when the application restarts.
+
+use error::EmailErrorKind;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+/// Reasons of the failed emails, the same classification the
+/// errors carry plus a catch-all for the ones that are not classified
+const REASONS: [&str; 4] = ["transient", "permanent", "connection", "unknown"];
+
+#[derive(Default)]
+pub struct EmailMetrics {
+    sent: AtomicU64,
+    failed: [AtomicU64; REASONS.len()],
+}
+
+/// Point in time copy of the counters
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct EmailMetricsSnapshot {
+    pub sent_total: u64,
+    pub failed_total: FailedTotal,
+}
+
+/// Failed emails by the reason they failed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct FailedTotal {
+    pub transient: u64,
+    pub permanent: u64,
+    pub connection: u64,
+    pub unknown: u64,
+}
+
+impl EmailMetrics {
+    /// Counters shared by all the senders
+    pub fn global() -> &'static EmailMetrics {
+        static METRICS: OnceLock<EmailMetrics> = OnceLock::new();
+
+        METRICS.get_or_init(EmailMetrics::default)
+    }
+
+    pub fn record_sent(&self) {
+        self.sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_failed(&self, kind: Option<EmailErrorKind>) {
+        let index = match kind {
+            Some(EmailErrorKind::Transient) => 0,
+            Some(EmailErrorKind::Permanent) => 1,
+            Some(EmailErrorKind::Connection) => 2,
+            None => 3,
+        };
+
+        self.failed[index].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> EmailMetricsSnapshot {
+        let failed = |index: usize| self.failed[index].load(Ordering::Relaxed);
+
+        EmailMetricsSnapshot {
+            sent_total: self.sent.load(Ordering::Relaxed),
+            failed_total: FailedTotal {
+                transient: failed(0),
+                permanent: failed(1),
+                connection: failed(2),
+                unknown: failed(3),
+            },
+        }
+    }
+
+    /// Counters in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+
+        output.push_str("# HELP hoodik_emails_sent_total Number of emails accepted by the relay\n");
+        output.push_str("# TYPE hoodik_emails_sent_total counter\n");
+        output.push_str(&format!(
+            "hoodik_emails_sent_total {}\n",
+            self.sent.load(Ordering::Relaxed)
+        ));
+
+        output.push_str("# HELP hoodik_emails_failed_total Number of emails that failed to send\n");
+        output.push_str("# TYPE hoodik_emails_failed_total counter\n");
+
+        for (reason, counter) in REASONS.iter().zip(self.failed.iter()) {
+            output.push_str(&format!(
+                "hoodik_emails_failed_total{{reason=\"{}\"}} {}\n",
+                reason,
+                counter.load(Ordering::Relaxed)
+            ));
+        }
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::EmailMetrics;
+    use error::EmailErrorKind;
+
+    #[test]
+    fn counters_are_rendered_by_reason() {
+        let metrics = EmailMetrics::default();
+
+        metrics.record_sent();
+        metrics.record_sent();
+        metrics.record_failed(Some(EmailErrorKind::Transient));
+        metrics.record_failed(None);
+
+        let snapshot = metrics.snapshot();
+
+        assert_eq!(snapshot.sent_total, 2);
+        assert_eq!(snapshot.failed_total.transient, 1);
+        assert_eq!(snapshot.failed_total.permanent, 0);
+        assert_eq!(snapshot.failed_total.unknown, 1);
+
+        let rendered = metrics.render();
+
+        assert!(rendered.contains("hoodik_emails_sent_total 2\n"));
+        assert!(rendered.contains("hoodik_emails_failed_total{reason=\"transient\"} 1\n"));
+        assert!(rendered.contains("hoodik_emails_failed_total{reason=\"connection\"} 0\n"));
+    }
+}
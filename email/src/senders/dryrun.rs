@@ -0,0 +1,212 @@
+use crate::contract::{SenderCapabilities, SenderContract};
+use crate::template::Template;
+use error::AppResult;
+use lettre::message::Mailbox;
+
+/// Sender that goes through the whole process of creating the email,
+/// but instead of sending it, it only logs the recipients and the subject.
+/// Nothing is sent or stored anywhere.
+#[derive(Clone)]
+pub struct DryRunSender {
+    default_from: Mailbox,
+}
+
+impl DryRunSender {
+    pub fn new(app_name: &str) -> AppResult<Self> {
+        Ok(Self {
+            default_from: Mailbox::new(Some(app_name.to_string()), "dryrun@localhost".parse()?),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl SenderContract for DryRunSender {
+    async fn send(&self, emails: Vec<Template>) -> AppResult<usize> {
+        let mut sent = 0;
+
+        for mut email in emails {
+            // Counted as sent without being logged, the same as the real senders skip it,
+            // so the dry run shows only what would really go out
+            if email.skip_send() {
+                sent += 1;
+                continue;
+            }
+
+            email = email.default_from(&self.default_from);
+
+            let message = email.message()?;
+
+            let recipients = message
+                .envelope()
+                .to()
+                .iter()
+                .map(|address| address.to_string())
+                .collect::<Vec<String>>()
+                .join(", ");
+
+            let subject = message
+                .headers()
+                .get_raw("Subject")
+                .unwrap_or_default()
+                .to_string();
+
+            log::info!("Dry run email to: {}, subject: {}", recipients, subject);
+
+            sent += 1;
+        }
+
+        Ok(sent)
+    }
+
+    /// The whole message is built as it would be for SMTP, it just never leaves the server
+    fn capabilities(&self) -> SenderCapabilities {
+        SenderCapabilities {
+            supports_attachments: true,
+            supports_bcc: true,
+            supports_connection_test: false,
+            is_real_delivery: false,
+        }
+    }
+
+    fn boxed_clone(&self) -> Box<dyn SenderContract> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DryRunSender;
+    use crate::contract::SenderContract;
+    use crate::template::{EmailCategory, Template};
+    use std::sync::Mutex;
+
+    /// Logger that keeps the messages, so the tests can check what the dry run logged
+    struct CapturingLogger {
+        messages: Mutex<Vec<String>>,
+    }
+
+    impl log::Log for CapturingLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            self.messages
+                .lock()
+                .unwrap()
+                .push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: CapturingLogger = CapturingLogger {
+        messages: Mutex::new(vec![]),
+    };
+
+    /// Messages logged by all the tests so far, the tests run in parallel
+    fn logged() -> Vec<String> {
+        if log::set_logger(&LOGGER).is_ok() {
+            log::set_max_level(log::LevelFilter::Info);
+        }
+
+        LOGGER.messages.lock().unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn dry_run_renders_and_counts_emails() {
+        let sender = DryRunSender::new("Hoodik").unwrap();
+
+        let mut template = Template::new("subject", "pre_header").unwrap();
+        template.register_content_template("content").unwrap();
+
+        let emails = vec![
+            template.clone().to("first@doe.com").unwrap(),
+            template.to("second@doe.com").unwrap(),
+        ];
+
+        assert_eq!(sender.send(emails).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn dry_run_skips_the_emails_the_real_senders_skip() {
+        logged();
+
+        let sender = DryRunSender::new("Hoodik").unwrap();
+
+        let mut template = Template::new("subject", "pre_header").unwrap();
+        template.register_content_template("content").unwrap();
+
+        let emails = vec![
+            template.clone().to("skipped@test.com").unwrap(),
+            template.to("logged@doe.com").unwrap(),
+        ];
+
+        assert_eq!(sender.send(emails).await.unwrap(), 2);
+
+        let logged = logged();
+
+        assert!(logged
+            .iter()
+            .any(|message| message.contains("logged@doe.com")));
+        assert!(!logged
+            .iter()
+            .any(|message| message.contains("skipped@test.com")));
+    }
+
+    #[tokio::test]
+    async fn dry_run_skips_the_notifications_of_opted_out_users() {
+        logged();
+
+        let sender = DryRunSender::new("Hoodik").unwrap();
+
+        let mut template = Template::new("subject", "pre_header").unwrap();
+        template.register_content_template("content").unwrap();
+
+        let notification = template
+            .clone()
+            .to("opted-out@doe.com")
+            .unwrap()
+            .category(EmailCategory::Notification)
+            .opt_out();
+
+        // Transactional emails are not affected by the opt out
+        let transactional = template.to("transactional@doe.com").unwrap().opt_out();
+
+        assert_eq!(
+            sender
+                .send(vec![notification, transactional])
+                .await
+                .unwrap(),
+            2
+        );
+
+        let logged = logged();
+
+        assert!(!logged
+            .iter()
+            .any(|message| message.contains("opted-out@doe.com")));
+        assert!(logged
+            .iter()
+            .any(|message| message.contains("transactional@doe.com")));
+    }
+
+    #[test]
+    fn dry_run_is_not_a_real_delivery() {
+        let capabilities = DryRunSender::new("Hoodik").unwrap().capabilities();
+
+        assert!(!capabilities.is_real_delivery);
+        assert!(!capabilities.supports_connection_test);
+        assert!(capabilities.supports_attachments);
+    }
+
+    #[tokio::test]
+    async fn dry_run_fails_on_emails_that_can_not_be_built() {
+        let sender = DryRunSender::new("Hoodik").unwrap();
+
+        // Missing recipient
+        let template = Template::new("subject", "pre_header").unwrap();
+
+        assert!(sender.send(vec![template]).await.is_err());
+    }
+}
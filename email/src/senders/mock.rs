@@ -1,20 +1,27 @@
 use std::sync::{Arc, Mutex};
 
-use crate::contract::SenderContract;
+use crate::contract::{SenderCapabilities, SenderContract};
 use crate::template::Template;
 use error::AppResult;
 
+/// Sender that records the emails instead of sending them, the clones share
+/// the records so a clone can be kept to inspect what the application sent.
 #[derive(Clone)]
 pub struct MockSender {
-    sent_subjects: Arc<Mutex<Vec<String>>>,
+    sent: Arc<Mutex<Vec<Template>>>,
 }
 
 impl MockSender {
     pub fn new() -> Self {
         Self {
-            sent_subjects: Arc::new(Mutex::new(vec![])),
+            sent: Arc::new(Mutex::new(vec![])),
         }
     }
+
+    /// Templates of all the sent emails, in the order they were sent
+    pub fn sent(&self) -> Vec<Template> {
+        self.sent.lock().unwrap().clone()
+    }
 }
 
 impl Default for MockSender {
@@ -29,46 +36,50 @@ impl SenderContract for MockSender {
         let len = emails.len();
 
         for mut email in emails {
+            // Counted as sent without being recorded, the same as the real senders skip it
+            if email.skip_send() {
+                continue;
+            }
+
             if !email.has_from() {
                 email = email.from("Mock Test <mock@test.com>")?;
             }
 
-            let email = email.message()?;
+            // Fails the same way the real senders fail on an email that can't be built
+            email.message()?;
 
-            let message = String::from_utf8(email.formatted())
-                .unwrap()
-                .split("\r\n")
-                .filter(|s| s.contains("Subject"))
-                .collect::<Vec<&str>>()
-                .join("");
-
-            let subject = message.replace("Subject: ", "");
-
-            self.sent_subjects.lock().unwrap().push(subject);
+            self.sent.lock().unwrap().push(email);
         }
 
         Ok(len)
     }
 
+    fn capabilities(&self) -> SenderCapabilities {
+        SenderCapabilities {
+            supports_attachments: true,
+            supports_bcc: true,
+            supports_connection_test: false,
+            is_real_delivery: false,
+        }
+    }
+
     fn has(&self, subject: &str) -> bool {
-        return self
-            .sent_subjects
+        self.sent
             .lock()
             .unwrap()
-            .contains(&subject.to_owned());
+            .iter()
+            .any(|email| email.subject() == subject)
     }
 
     #[cfg(feature = "mock")]
     fn find(&self, pat: &str) -> Option<String> {
-        let sent_subjects = self.sent_subjects.lock().unwrap();
-
-        for subject in sent_subjects.iter() {
-            if subject.contains(pat) {
-                return Some(subject.to_string());
-            }
-        }
-
-        None
+        self.sent
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|email| email.subject())
+            .find(|subject| subject.contains(pat))
+            .map(|subject| subject.to_string())
     }
 
     fn boxed_clone(&self) -> Box<dyn SenderContract> {
@@ -1,4 +1,7 @@
+pub mod dryrun;
+mod proxy;
 pub mod smtp;
+pub mod webhook;
 
 #[cfg(feature = "mock")]
 pub mod mock;
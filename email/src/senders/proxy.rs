@@ -0,0 +1,358 @@
+//! SMTP connections tunneled through a SOCKS5 proxy (RFC 1928). The pooled lettre
+//! transport can only connect to the relay directly, so every message sent through
+//! the proxy opens its own tunnel and SMTP connection.
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use config::email::{SmtpCredentials, SmtpProxy, TlsMode};
+use error::Error;
+use lettre::transport::smtp::{
+    authentication::{Credentials, Mechanism},
+    client::{AsyncSmtpConnection, AsyncTokioStream, TlsParameters},
+    extension::ClientId,
+    response::Response,
+    Error as SmtpError,
+};
+use lettre::{address::Envelope, Message};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::TcpStream;
+
+use super::smtp::{tls_failure, SmtpSender};
+
+/// Time allowed for reaching the relay through the proxy
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Error of a message sent through the proxy, it tells apart the proxy
+/// that can't be reached from the relay that can't be reached through it.
+#[derive(Debug)]
+pub(crate) enum ProxyError {
+    /// The proxy can't be reached or it rejected us
+    Proxy(String),
+    /// The proxy works, but it couldn't reach the relay
+    Relay(String),
+    /// The relay was reached, the SMTP conversation failed
+    Smtp(SmtpError),
+}
+
+impl From<SmtpError> for ProxyError {
+    fn from(e: SmtpError) -> Self {
+        ProxyError::Smtp(e)
+    }
+}
+
+impl From<ProxyError> for Error {
+    fn from(e: ProxyError) -> Self {
+        match e {
+            ProxyError::Proxy(message) => {
+                Error::InternalError(format!("smtp_proxy_unreachable: {message}"))
+            }
+            ProxyError::Relay(message) => {
+                Error::InternalError(format!("smtp_relay_unreachable_through_proxy: {message}"))
+            }
+            ProxyError::Smtp(e) => Error::from(e),
+        }
+    }
+}
+
+/// Relay that is reached through the SOCKS5 proxy
+pub(crate) struct ProxiedRelay {
+    proxy: SmtpProxy,
+    address: String,
+    port: u16,
+    /// Hostname the implicit TLS handshake is made for
+    tls_domain: String,
+    tls_mode: TlsMode,
+    tls_parameters: TlsParameters,
+    hello_name: ClientId,
+    credentials: Option<Credentials>,
+}
+
+impl ProxiedRelay {
+    pub(crate) fn new(
+        proxy: &SmtpProxy,
+        credentials: &SmtpCredentials,
+        hello_name: ClientId,
+        smtp_credentials: Option<Credentials>,
+    ) -> Result<Self, SmtpError> {
+        Ok(Self {
+            proxy: proxy.clone(),
+            address: credentials.address.to_string(),
+            port: credentials.port,
+            tls_domain: SmtpSender::tls_domain(credentials).to_string(),
+            tls_mode: credentials.tls_mode.clone(),
+            tls_parameters: SmtpSender::tls_parameters(credentials)?,
+            hello_name,
+            credentials: smtp_credentials,
+        })
+    }
+
+    /// Send the message over a new connection through the proxy
+    pub(crate) async fn send(
+        &self,
+        envelope: &Envelope,
+        message: &Message,
+    ) -> Result<Response, ProxyError> {
+        let mut conn = self.connect().await?;
+
+        let response = conn.send(envelope, &message.formatted()).await?;
+
+        let _ = conn.quit().await;
+
+        Ok(response)
+    }
+
+    /// Open a connection to the relay through the proxy and close it right away
+    pub(crate) async fn test_connection(&self) -> Result<(), ProxyError> {
+        let mut conn = self.connect().await?;
+
+        conn.quit().await?;
+
+        Ok(())
+    }
+
+    /// Open the SMTP connection through the tunnel, the same way the transport
+    /// does for the TLS mode, and authenticate if there are credentials.
+    async fn connect(&self) -> Result<AsyncSmtpConnection, ProxyError> {
+        let stream = match tokio::time::timeout(CONNECT_TIMEOUT, self.tunnel()).await {
+            Ok(stream) => stream?,
+            Err(_) => {
+                return Err(ProxyError::Proxy(format!(
+                    "timed out reaching {}:{} through the proxy",
+                    self.address, self.port
+                )))
+            }
+        };
+
+        let mut conn = match &self.tls_mode {
+            TlsMode::ImplicitTls => {
+                // The native TLS backend can't require more than TLS 1.2, the only
+                // value SMTP_MIN_TLS_VERSION accepts
+                let connector = native_tls::TlsConnector::builder()
+                    .min_protocol_version(Some(native_tls::Protocol::Tlsv12))
+                    .build()
+                    .map_err(|e| ProxyError::Relay(format!("tls setup failed: {e}")))?;
+
+                let stream = tokio_native_tls::TlsConnector::from(connector)
+                    .connect(&self.tls_domain, stream)
+                    .await
+                    .map_err(|e| {
+                        let (failure, hint) = tls_failure(&e.to_string());
+
+                        ProxyError::Relay(format!("tls handshake failed ({failure}): {e}; {hint}"))
+                    })?;
+
+                AsyncSmtpConnection::connect_with_transport(
+                    Box::new(TlsTunnel(stream)),
+                    &self.hello_name,
+                )
+                .await?
+            }
+            TlsMode::StartTls => {
+                let mut conn =
+                    AsyncSmtpConnection::connect_with_transport(Box::new(stream), &self.hello_name)
+                        .await?;
+
+                conn.starttls(self.tls_parameters.clone(), &self.hello_name)
+                    .await?;
+
+                conn
+            }
+            TlsMode::None => {
+                AsyncSmtpConnection::connect_with_transport(Box::new(stream), &self.hello_name)
+                    .await?
+            }
+        };
+
+        if let Some(credentials) = self.credentials.as_ref() {
+            let mechanisms = [Mechanism::Plain, Mechanism::Login];
+
+            conn.auth(&mechanisms, credentials).await?;
+        }
+
+        Ok(conn)
+    }
+
+    /// Connect to the proxy and ask it to connect to the relay, the relay hostname
+    /// is sent as it is so it gets resolved by the proxy.
+    async fn tunnel(&self) -> Result<TcpStream, ProxyError> {
+        let proxy_error = |e: std::io::Error| {
+            ProxyError::Proxy(format!("{}:{}: {}", self.proxy.host, self.proxy.port, e))
+        };
+
+        let mut stream = TcpStream::connect((self.proxy.host.as_str(), self.proxy.port))
+            .await
+            .map_err(proxy_error)?;
+
+        // Greeting with the authentication methods we support
+        let methods: &[u8] = match self.proxy.username.is_some() {
+            true => &[0x05, 0x02, 0x00, 0x02],
+            false => &[0x05, 0x01, 0x00],
+        };
+
+        stream.write_all(methods).await.map_err(proxy_error)?;
+
+        let mut reply = [0u8; 2];
+        stream.read_exact(&mut reply).await.map_err(proxy_error)?;
+
+        match reply {
+            [0x05, 0x00] => (),
+            [0x05, 0x02] => self.authenticate(&mut stream).await?,
+            [0x05, 0xff] => {
+                return Err(ProxyError::Proxy(
+                    "proxy accepts none of the offered authentication methods".to_string(),
+                ))
+            }
+            _ => {
+                return Err(ProxyError::Proxy(
+                    "proxy did not answer as a SOCKS5 proxy".to_string(),
+                ))
+            }
+        }
+
+        let host = self.address.as_bytes();
+
+        let length = u8::try_from(host.len())
+            .map_err(|_| ProxyError::Relay("relay hostname is too long".to_string()))?;
+
+        // CONNECT with the relay given as a domain name
+        let mut request = vec![0x05, 0x01, 0x00, 0x03, length];
+        request.extend_from_slice(host);
+        request.extend_from_slice(&self.port.to_be_bytes());
+
+        stream.write_all(&request).await.map_err(proxy_error)?;
+
+        let mut reply = [0u8; 4];
+        stream.read_exact(&mut reply).await.map_err(proxy_error)?;
+
+        if reply[1] != 0x00 {
+            return Err(ProxyError::Relay(format!(
+                "{}:{}: {}",
+                self.address,
+                self.port,
+                reply_message(reply[1])
+            )));
+        }
+
+        // The address the proxy bound for the connection, we don't need it
+        let bound = match reply[3] {
+            0x01 => 4,
+            0x04 => 16,
+            0x03 => {
+                let mut length = [0u8; 1];
+                stream.read_exact(&mut length).await.map_err(proxy_error)?;
+
+                length[0] as usize
+            }
+            _ => {
+                return Err(ProxyError::Proxy(
+                    "proxy replied with an unknown address type".to_string(),
+                ))
+            }
+        };
+
+        let mut address = vec![0u8; bound + 2];
+        stream.read_exact(&mut address).await.map_err(proxy_error)?;
+
+        Ok(stream)
+    }
+
+    /// Username and password authentication (RFC 1929)
+    async fn authenticate(&self, stream: &mut TcpStream) -> Result<(), ProxyError> {
+        let username = self
+            .proxy
+            .username
+            .as_deref()
+            .unwrap_or_default()
+            .as_bytes();
+        let password = self
+            .proxy
+            .password
+            .as_deref()
+            .unwrap_or_default()
+            .as_bytes();
+
+        let (Ok(username_length), Ok(password_length)) =
+            (u8::try_from(username.len()), u8::try_from(password.len()))
+        else {
+            return Err(ProxyError::Proxy(
+                "proxy username or password is too long".to_string(),
+            ));
+        };
+
+        let mut request = vec![0x01, username_length];
+        request.extend_from_slice(username);
+        request.push(password_length);
+        request.extend_from_slice(password);
+
+        let io_error = |e: std::io::Error| ProxyError::Proxy(e.to_string());
+
+        stream.write_all(&request).await.map_err(io_error)?;
+
+        let mut reply = [0u8; 2];
+        stream.read_exact(&mut reply).await.map_err(io_error)?;
+
+        if reply[1] != 0x00 {
+            return Err(ProxyError::Proxy(
+                "proxy rejected the username and password".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Meaning of the SOCKS5 reply codes
+fn reply_message(code: u8) -> &'static str {
+    match code {
+        0x01 => "general proxy failure",
+        0x02 => "connection not allowed by the proxy rules",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "ttl expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown proxy error",
+    }
+}
+
+/// TLS stream over the tunnel for the implicit TLS mode, lettre can only
+/// encrypt the custom streams with STARTTLS.
+#[derive(Debug)]
+struct TlsTunnel(tokio_native_tls::TlsStream<TcpStream>);
+
+impl AsyncRead for TlsTunnel {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TlsTunnel {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+impl AsyncTokioStream for TlsTunnel {
+    fn peer_addr(&self) -> std::io::Result<std::net::SocketAddr> {
+        self.0.get_ref().get_ref().get_ref().peer_addr()
+    }
+}
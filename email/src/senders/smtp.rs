@@ -1,106 +1,2129 @@
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use crate::metrics::EmailMetrics;
 use crate::template::Template;
-use config::email::TlsMode;
-use error::{AppResult, Error};
-use lettre::message::Mailbox;
+use config::email::{is_allowed_from, DkimCredentials, MinTlsVersion, SmtpCredentials, TlsMode};
+use error::{AppResult, EmailErrorKind, Error};
+use lettre::message::{dkim::DkimConfig, Mailbox};
+use lettre::transport::smtp::SmtpTransportBuilder;
+use lettre::transport::smtp::{
+    authentication::{Credentials, Mechanism},
+    client::{SmtpConnection, Tls, TlsParameters, TlsVersion},
+    commands::{Mail, Quit, Rcpt},
+    extension::ClientId,
+    response::{Response, Severity},
+    Error as SmtpError, PoolConfig,
+};
 use lettre::Transport as _;
-use lettre::{transport::smtp::authentication::Credentials, SmtpTransport};
+use lettre::{address::Envelope, Address, Message, SmtpTransport};
+use tokio::sync::Semaphore;
 
-use crate::contract::SenderContract;
+use super::proxy::{ProxiedRelay, ProxyError};
+use crate::contract::{RelayResponse, SendOutcome, SenderCapabilities, SenderContract};
+
+/// Pause before retrying a failed delivery, so a relay that just dropped
+/// the connection has a moment before the next one
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Recipient of the open relay check, the reserved `.invalid` domain
+/// can't be hosted by the relay we check
+const OPEN_RELAY_PROBE_RECIPIENT: &str = "postmaster@example.invalid";
 
 #[derive(Clone)]
 pub struct SmtpSender {
     smtp: SmtpTransport,
+    /// Transport builder for the connections of every part of a batch split by the max batch size
+    transport: SmtpTransportBuilder,
+    /// Messages of a batch sent over the same connections, no limit when not set
+    max_batch_size: Option<usize>,
+    /// Address and port of the relay, to tell what couldn't be reached
+    relay: (String, u16),
+    /// Domains the From can be in, any domain when empty
+    allowed_from_domains: Vec<String>,
+    /// A From in any other domain fails the email instead of using the default from
+    strict_from_domains: bool,
+    /// Oldest TLS version offered to the relay, to tell why the handshake failed
+    min_tls_version: MinTlsVersion,
     default_from: Mailbox,
+    validate_before_batch: bool,
+    concurrency: usize,
+    send_timeout: Duration,
+    message_id_domain: Option<String>,
+    /// Sender of the envelope (MAIL FROM) when it differs from the From header
+    envelope_from: Option<Address>,
+    bcc_archive: Option<Mailbox>,
+    dkim: Option<Arc<DkimConfig>>,
+    proxy: Option<Arc<ProxiedRelay>>,
+    metrics: &'static EmailMetrics,
 }
 
 impl SmtpSender {
-    pub fn new(
+    #[tracing::instrument(
+        name = "smtp_new",
+        skip_all,
+        fields(
+            address = %credentials.address,
+            port = credentials.port,
+            tls_mode = ?credentials.tls_mode,
+        )
+    )]
+    pub fn new(credentials: &SmtpCredentials) -> AppResult<Self> {
+        let address = credentials.address.as_str();
+        let port = credentials.port;
+
+        let builder = SmtpTransport::builder_dangerous(address);
+
+        let builder = match &credentials.tls_mode {
+            // STARTTLS - typically port 587
+            TlsMode::StartTls => builder.tls(Tls::Required(Self::tls_parameters(credentials)?)),
+            // Implicit TLS (wrapper mode) - typically port 465
+            TlsMode::ImplicitTls => builder.tls(Tls::Wrapper(Self::tls_parameters(credentials)?)),
+            // No TLS - typically port 25 (development only)
+            TlsMode::None => builder,
+        };
+
+        let mut builder = builder
+            .port(port)
+            .hello_name(Self::hello_name(credentials))
+            .pool_config(PoolConfig::new().idle_timeout(credentials.idle_timeout));
+
+        if let Some(smtp_credentials) = Self::credentials(credentials) {
+            builder = builder.credentials(smtp_credentials);
+        }
+
+        let smtp = builder.clone().build();
+
+        let proxy = credentials
+            .proxy
+            .as_ref()
+            .map(|proxy| {
+                ProxiedRelay::new(
+                    proxy,
+                    credentials,
+                    Self::hello_name(credentials),
+                    Self::credentials(credentials),
+                )
+                .map(Arc::new)
+            })
+            .transpose()?;
+
+        if proxy.is_some() {
+            // The sender is created outside of the async context, so the connection through
+            // the proxy can't be tested here, the server tests it in the background once it started
+            if credentials.verify_on_startup {
+                log::warn!(
+                    "Skipping SMTP connection verification on startup for {address}:{port}, \
+                    the relay is reached through SMTP_PROXY_URL"
+                );
+            }
+        } else {
+            if credentials.verify_auth {
+                Self::verify_authentication(credentials)?;
+            }
+
+            Self::verify_connection(&smtp, credentials)?;
+        }
+
+        Ok(Self {
+            smtp,
+            transport: builder,
+            max_batch_size: credentials.max_batch_size.filter(|size| *size > 0),
+            relay: (address.to_string(), port),
+            min_tls_version: credentials.min_tls_version,
+            allowed_from_domains: credentials.allowed_from_domains.clone(),
+            strict_from_domains: credentials.strict_from_domains,
+            default_from: Mailbox::from_str(&credentials.default_from)?,
+            validate_before_batch: credentials.validate_before_batch,
+            concurrency: credentials.concurrency.max(1),
+            send_timeout: credentials.send_timeout,
+            message_id_domain: credentials.message_id_domain.clone(),
+            envelope_from: credentials
+                .envelope_from
+                .as_deref()
+                .map(Address::from_str)
+                .transpose()?,
+            bcc_archive: credentials
+                .bcc_archive
+                .as_deref()
+                .map(Mailbox::from_str)
+                .transpose()?,
+            dkim: credentials.dkim.as_ref().map(Self::dkim).transpose()?,
+            proxy,
+            metrics: EmailMetrics::global(),
+        })
+    }
+
+    /// Test the connection to the relay on startup, unless SMTP_VERIFY_ON_STARTUP is disabled,
+    /// the config already warns about that and the server tests it in the background instead
+    fn verify_connection(smtp: &SmtpTransport, credentials: &SmtpCredentials) -> AppResult<()> {
+        let address = credentials.address.as_str();
+        let port = credentials.port;
+
+        if !credentials.verify_on_startup {
+            return Ok(());
+        }
+
+        if !credentials.username.is_empty() {
+            match Self::diagnose_authentication(credentials) {
+                Ok(Some(warning)) => log::warn!("{warning}"),
+                Ok(None) => (),
+                Err(e) => log::debug!("Smtp authentication diagnostic failed: {e}"),
+            }
+        }
+
+        smtp.test_connection()
+            .map_err(|e| Self::connection_error(address, port, credentials.min_tls_version, e))?;
+
+        Ok(())
+    }
+
+    /// Authenticate with the relay over a connection of its own and close it, a relay
+    /// can accept the connection and still reject the credentials once an email is sent.
+    /// Without a username there is nothing to verify.
+    fn verify_authentication(credentials: &SmtpCredentials) -> AppResult<()> {
+        let address = credentials.address.as_str();
+        let port = credentials.port;
+
+        let Some(smtp_credentials) = Self::credentials(credentials) else {
+            return Ok(());
+        };
+
+        let mut conn = Self::probe_connection(credentials)
+            .map_err(|e| Self::connection_error(address, port, credentials.min_tls_version, e))?;
+
+        let result = conn.auth(&[Mechanism::Plain, Mechanism::Login], &smtp_credentials);
+
+        let _ = conn.command(Quit);
+
+        result.map_err(|e| {
+            Error::SmtpConnection(format!(
+                "SMTP authentication failed for '{}' at {address}:{port}: {e}; check SMTP_USERNAME/SMTP_PASSWORD",
+                credentials.username
+            ))
+        })?;
+
+        Ok(())
+    }
+
+    /// Tell apart why the relay couldn't be reached, lettre reports a hostname that doesn't
+    /// resolve the same way as any other connection error, but a typo in SMTP_ADDRESS is
+    /// the most common mistake. Errors that are not about the connection are left as they are.
+    fn connection_error(
         address: &str,
-        username: &str,
-        password: &str,
         port: u16,
-        tls_mode: &TlsMode,
-        default_from: &str,
-    ) -> AppResult<Self> {
-        let credentials = Credentials::new(username.to_string(), password.to_string());
+        min_tls_version: MinTlsVersion,
+        e: SmtpError,
+    ) -> Error {
+        let io_error =
+            std::iter::successors(std::error::Error::source(&e), |source| source.source())
+                .find_map(|source| source.downcast_ref::<std::io::Error>());
+
+        // lettre reports a handshake that fails while connecting as a connection error
+        let handshake =
+            std::iter::successors(std::error::Error::source(&e), |source| source.source()).find(
+                |source| {
+                    source.is::<native_tls::Error>()
+                        || source.is::<native_tls::HandshakeError<std::net::TcpStream>>()
+                },
+            );
+
+        let message = if e.is_tls() || handshake.is_some() {
+            // The TLS backend only describes the failure in the message of the innermost error
+            let detail =
+                std::iter::successors(std::error::Error::source(&e), |source| source.source())
+                    .last()
+                    .map(|source| source.to_string())
+                    .unwrap_or_else(|| e.to_string());
+
+            let (failure, hint) = tls_failure(&detail);
+
+            format!(
+                "TLS handshake failed ({failure}) connecting to {address}:{port} with TLS {} or newer: {detail}; {hint}",
+                min_tls_version.as_str()
+            )
+        } else if e.is_timeout() {
+            format!("Timed out connecting to the SMTP relay {address}:{port}; check SMTP_PORT and the firewall")
+        } else if io_error.is_some_and(|io| io.kind() == std::io::ErrorKind::ConnectionRefused) {
+            format!("SMTP relay {address}:{port} refused the connection; check SMTP_PORT")
+        } else if Self::is_resolution_error(&e, io_error) {
+            format!("Could not resolve SMTP host '{address}'; check SMTP_ADDRESS")
+        } else {
+            return Error::from(e);
+        };
+
+        Error::SmtpConnection(message)
+    }
+
+    /// The hostname of the relay didn't resolve, the resolver only tells it in the message
+    fn is_resolution_error(e: &SmtpError, io_error: Option<&std::io::Error>) -> bool {
+        let message = match io_error {
+            Some(io) => io.to_string(),
+            None => e.to_string(),
+        };
+
+        message.contains("failed to lookup address")
+            || message.contains("could not resolve to any address")
+    }
+
+    /// DKIM signing of the messages with the default set of signed headers
+    fn dkim(dkim: &DkimCredentials) -> AppResult<Arc<DkimConfig>> {
+        let key = dkim
+            .signing_key()
+            .map_err(|e| Error::InternalError(format!("invalid_dkim_private_key: {e}")))?;
+
+        Ok(Arc::new(DkimConfig::default_config(
+            dkim.selector.to_string(),
+            dkim.domain.to_string(),
+            key,
+        )))
+    }
+
+    /// Credentials to authenticate with, relays without authentication
+    /// are configured with an empty username and get no credentials at all.
+    fn credentials(credentials: &SmtpCredentials) -> Option<Credentials> {
+        if credentials.username.is_empty() {
+            return None;
+        }
+
+        Some(Credentials::new(
+            credentials.username.to_string(),
+            credentials.password.to_string(),
+        ))
+    }
+
+    /// TLS parameters for the relay with the configured minimum TLS version, the certificate
+    /// is validated for SMTP_TLS_SNI_HOST when set, otherwise for the address we connect to.
+    pub(crate) fn tls_parameters(
+        credentials: &SmtpCredentials,
+    ) -> Result<TlsParameters, SmtpError> {
+        let min_tls_version = match credentials.min_tls_version {
+            MinTlsVersion::Tls12 => TlsVersion::Tlsv12,
+        };
+
+        TlsParameters::builder(Self::tls_domain(credentials).to_string())
+            .set_min_tls_version(min_tls_version)
+            .build()
+    }
+
+    /// Hostname the relay is asked for in SNI and its certificate is validated against
+    pub(crate) fn tls_domain(credentials: &SmtpCredentials) -> &str {
+        credentials
+            .tls_sni_host
+            .as_deref()
+            .unwrap_or(&credentials.address)
+    }
+
+    /// Name announced in EHLO, falls back to lettre's default (hostname of the machine)
+    fn hello_name(credentials: &SmtpCredentials) -> ClientId {
+        match credentials.hello_name.as_deref() {
+            Some(name) => ClientId::Domain(name.to_string()),
+            None => ClientId::default(),
+        }
+    }
 
-        let smtp = match tls_mode {
+    /// Open a connection to the relay the same way the transport does,
+    /// only without authenticating, so we can inspect the server.
+    fn probe_connection(credentials: &SmtpCredentials) -> Result<SmtpConnection, SmtpError> {
+        let server = (credentials.address.as_str(), credentials.port);
+        let timeout = Some(credentials.send_timeout);
+        let hello_name = Self::hello_name(credentials);
+
+        match &credentials.tls_mode {
             TlsMode::StartTls => {
-                // STARTTLS - typically port 587
-                SmtpTransport::starttls_relay(address)?
-                    .port(port)
-                    .credentials(credentials)
-                    .build()
+                let tls = Self::tls_parameters(credentials)?;
+                let mut conn = SmtpConnection::connect(server, timeout, &hello_name, None, None)?;
+                conn.starttls(&tls, &hello_name)?;
+
+                Ok(conn)
             }
             TlsMode::ImplicitTls => {
-                // Implicit TLS (wrapper mode) - typically port 465
-                SmtpTransport::relay(address)?
-                    .port(port)
-                    .credentials(credentials)
-                    .build()
+                let tls = Self::tls_parameters(credentials)?;
+
+                SmtpConnection::connect(server, timeout, &hello_name, Some(&tls), None)
+            }
+            TlsMode::None => SmtpConnection::connect(server, timeout, &hello_name, None, None),
+        }
+    }
+
+    /// Check that the relay actually requires the credentials we have configured,
+    /// a relay that ignores them or accepts mail without authentication
+    /// is either misconfigured or an open relay.
+    ///
+    /// Returns a warning describing the problem if one was detected.
+    pub fn diagnose_authentication(credentials: &SmtpCredentials) -> AppResult<Option<String>> {
+        let relay = format!("{}:{}", credentials.address, credentials.port);
+        let mut conn = Self::probe_connection(credentials)?;
+
+        let mechanisms = [Mechanism::Plain, Mechanism::Login, Mechanism::Xoauth2];
+
+        if conn.server_info().get_auth_mechanism(&mechanisms).is_none() {
+            conn.abort();
+
+            return Ok(Some(format!(
+                "SMTP relay {relay} does not advertise AUTH although SMTP_USERNAME is set, \
+                the credentials are ignored and the relay might be an open relay"
+            )));
+        }
+
+        // A relay is also the MX of its own domain, so only a recipient outside
+        // of its domains tells if it relays mail for anyone who connects
+        let address = Mailbox::from_str(&credentials.default_from)?.email;
+        let recipient = Address::from_str(OPEN_RELAY_PROBE_RECIPIENT)?;
+
+        let accepted = conn
+            .command(Mail::new(Some(address), vec![]))
+            .and_then(|_| conn.command(Rcpt::new(recipient, vec![])))
+            .is_ok();
+
+        let _ = conn.command(Quit);
+
+        if accepted {
+            return Ok(Some(format!(
+                "SMTP relay {relay} accepted a message without authentication, \
+                the configured credentials are not required and the relay might be an open relay"
+            )));
+        }
+
+        Ok(None)
+    }
+
+    /// Email without a single recipient is a bug of whoever created it, it is reported and
+    /// left out instead of failing the whole batch the way building its message would
+    fn missing_recipient(&self, email: &Template) -> Option<Error> {
+        if !email.recipients().is_empty() {
+            return None;
+        }
+
+        log::warn!(
+            "Skipping email '{}' without recipients, it was created without a to or bcc address",
+            email.subject()
+        );
+
+        self.metrics.record_failed(Some(EmailErrorKind::Permanent));
+
+        Some(Error::BadRequest("email_missing_recipient".to_string()))
+    }
+
+    /// Build the message out of the template, skipped emails produce no message
+    fn prepare(&self, mut email: Template) -> AppResult<Option<Outgoing>> {
+        let retry_policy = email.retry_policy();
+
+        email = email.default_from(&self.default_from);
+
+        if let Some(from) = email.from_address() {
+            if !is_allowed_from(&self.allowed_from_domains, &from.email) {
+                if self.strict_from_domains {
+                    return Err(Error::BadRequest(format!(
+                        "from_domain_not_allowed:{}",
+                        from.email.domain()
+                    )));
+                }
+
+                log::warn!(
+                    "From '{}' of the email '{}' is not in the ALLOWED_FROM_DOMAINS, sending it from '{}'",
+                    from.email,
+                    email.subject(),
+                    self.default_from
+                );
+
+                email = email.from_mailbox(&self.default_from);
             }
-            TlsMode::None => {
-                // No TLS - typically port 25 (development only)
-                SmtpTransport::builder_dangerous(address)
-                    .port(port)
-                    .credentials(credentials)
-                    .build()
+        }
+
+        if let Some(domain) = self.message_id_domain.as_deref() {
+            email = email.message_id_domain(domain);
+        }
+
+        // Bcc is only on the envelope, so the recipients never see the archive address
+        if let Some(archive) = self.bcc_archive.as_ref() {
+            email = email.bcc_mailbox(archive);
+        }
+
+        if email.skip_send() {
+            return Ok(None);
+        }
+
+        let mut message = email.message()?;
+
+        if let Some(dkim) = self.dkim.as_deref() {
+            message.sign(dkim);
+        }
+
+        // The relay sends the bounces to the envelope sender, the recipients only see the From header
+        let envelope = match self.envelope_from.as_ref() {
+            Some(from) => Envelope::new(Some(from.clone()), message.envelope().to().to_vec())?,
+            None => message.envelope().clone(),
+        };
+
+        Ok(Some(Outgoing {
+            message,
+            envelope,
+            retries: retry_policy.retries.unwrap_or_default(),
+            send_timeout: retry_policy.send_timeout.unwrap_or(self.send_timeout),
+        }))
+    }
+
+    /// Deliver the prepared messages, skipped emails (`None`) are not delivered and
+    /// have no response, the results are in the same order as the messages.
+    ///
+    /// With the max batch size the messages are delivered in parts of at most that many
+    /// messages one after another, every part over new connections that are closed once
+    /// it is delivered, so no connection carries more messages than the relay allows.
+    async fn deliver_all(
+        &self,
+        messages: Vec<Option<Outgoing>>,
+        stop_on_error: bool,
+    ) -> Vec<AppResult<Option<Response>>> {
+        if let Some(proxy) = self.proxy.as_ref() {
+            return deliver_through_proxy(proxy, messages, stop_on_error).await;
+        }
+
+        let results = match self.max_batch_size {
+            None => self.deliver_over(&self.smtp, messages, stop_on_error).await,
+            Some(max_batch_size) => {
+                let mut results = vec![];
+                let mut messages = messages.into_iter().peekable();
+
+                while messages.peek().is_some() {
+                    let part = messages.by_ref().take(max_batch_size).collect::<Vec<_>>();
+                    let smtp = self.transport.clone().build();
+
+                    let delivered = self.deliver_over(&smtp, part, stop_on_error).await;
+                    let failed = delivered.iter().any(Result::is_err);
+
+                    results.extend(delivered);
+
+                    if failed && stop_on_error {
+                        break;
+                    }
+                }
+
+                results
             }
         };
 
-        smtp.test_connection()?;
+        results
+            .into_iter()
+            .map(|result| result.map_err(Error::from))
+            .collect()
+    }
 
-        Ok(Self {
-            smtp,
-            default_from: Mailbox::from_str(default_from)?,
-        })
+    /// Deliver the messages over the connections of the transport.
+    ///
+    /// With `concurrency` above one at most that many messages are in flight at once
+    /// and every message is attempted even if some of them fail, otherwise the messages
+    /// are delivered one by one and `stop_on_error` stops at the first failed one.
+    /// Every message has its send timeout to be accepted, a message that takes longer
+    /// fails with a transient error instead of holding up the batch. Failures that can
+    /// be retried are attempted again as many times as the retry policy allows.
+    async fn deliver_over(
+        &self,
+        smtp: &SmtpTransport,
+        messages: Vec<Option<Outgoing>>,
+        stop_on_error: bool,
+    ) -> Vec<Result<Option<Response>, DeliveryError>> {
+        if self.concurrency <= 1 {
+            let mut results = vec![];
+
+            for message in messages {
+                let result = match message {
+                    Some(message) => deliver_with_retries(smtp.clone(), message).await.map(Some),
+                    None => Ok(None),
+                };
+
+                let failed = result.is_err();
+                results.push(result);
+
+                if failed && stop_on_error {
+                    break;
+                }
+            }
+
+            return results;
+        }
+
+        let semaphore = Arc::new(Semaphore::new(self.concurrency));
+        let mut tasks = vec![];
+
+        for message in messages {
+            let smtp = smtp.clone();
+            let semaphore = semaphore.clone();
+
+            tasks.push(tokio::spawn(async move {
+                let message = match message {
+                    Some(message) => message,
+                    None => return Ok(None),
+                };
+
+                // The semaphore is never closed so acquiring can't fail
+                let _permit = semaphore.acquire_owned().await.ok();
+
+                deliver_with_retries(smtp, message).await.map(Some)
+            }));
+        }
+
+        let mut joined = vec![];
+
+        for task in tasks {
+            joined.push(task.await);
+        }
+
+        joined
+            .into_iter()
+            .map(|result| match result {
+                Ok(result) => result,
+                Err(e) => Err(DeliveryError::Task(e.to_string())),
+            })
+            .collect()
+    }
+
+    /// Count the delivery in the metrics, skipped emails are not counted
+    fn record(&self, result: &AppResult<Option<Response>>) {
+        match result {
+            Ok(None) => (),
+            Ok(Some(response)) if response.is_positive() => self.metrics.record_sent(),
+            Ok(Some(response)) => {
+                let kind = match response.code().severity {
+                    Severity::TransientNegativeCompletion => EmailErrorKind::Transient,
+                    _ => EmailErrorKind::Permanent,
+                };
+
+                self.metrics.record_failed(Some(kind));
+            }
+            Err(e) => self.metrics.record_failed(e.email_error_kind()),
+        }
+    }
+
+    /// Make sure the relay is still answering before we start sending a batch,
+    /// checking out a connection from the pool will drop the stale ones and
+    /// open a fresh connection so the first message doesn't fail on it.
+    async fn validate_connection(&self) -> AppResult<()> {
+        // Messages sent through the proxy always open a new connection
+        if self.proxy.is_some() {
+            return Ok(());
+        }
+
+        match test_connection_in_time(self.smtp.clone(), self.send_timeout).await {
+            Ok(true) => (),
+            Ok(false) => log::warn!("Smtp connection failed validation before sending the batch"),
+            Err(e) => return Err(connection_test_error(e)),
+        }
+
+        Ok(())
+    }
+}
+
+/// What made the TLS handshake fail and what to check for it, told by the message of the TLS backend
+pub(crate) fn tls_failure(message: &str) -> (&'static str, &'static str) {
+    let message = message.to_lowercase();
+
+    if message.contains("wrong version number") || message.contains("packet length too long") {
+        (
+            "relay doesn't speak TLS",
+            "check SMTP_TLS_MODE, the port might expect starttls or none",
+        )
+    } else if message.contains("certificate") {
+        (
+            "certificate validation",
+            "check that the certificate of the relay is valid for SMTP_ADDRESS or SMTP_TLS_SNI_HOST",
+        )
+    } else if message.contains("protocol version") || message.contains("unsupported protocol") {
+        (
+            "protocol version",
+            "check SMTP_MIN_TLS_VERSION against the versions the relay offers",
+        )
+    } else if message.contains("cipher") || message.contains("handshake failure") {
+        (
+            "cipher mismatch",
+            "the relay offers no cipher the TLS backend accepts",
+        )
+    } else {
+        ("handshake", "check SMTP_TLS_MODE and SMTP_MIN_TLS_VERSION")
+    }
+}
+
+/// Message ready to be delivered with the retry policy of its template
+struct Outgoing {
+    message: Message,
+    /// Envelope the message is sent with, its sender can differ from the From header
+    envelope: Envelope,
+    retries: u32,
+    send_timeout: Duration,
+}
+
+/// Error of a single delivery, unlike `Error` it can be sent between the tasks
+#[derive(Debug)]
+enum DeliveryError {
+    Smtp(SmtpError),
+    /// The message was sent through the proxy
+    Proxy(ProxyError),
+    /// The relay didn't accept the message within the send timeout
+    Timeout(Duration),
+    /// The task delivering the message failed
+    Task(String),
+}
+
+impl DeliveryError {
+    /// Could another attempt succeed, the relay rejecting the message for good won't change.
+    ///
+    /// A timed out send is not retried, the attempt we stopped waiting for can't be
+    /// interrupted and might still deliver the message, another one could send it twice.
+    fn is_retryable(&self) -> bool {
+        match self {
+            DeliveryError::Smtp(e) | DeliveryError::Proxy(ProxyError::Smtp(e)) => {
+                EmailErrorKind::from(e).is_retryable()
+            }
+            DeliveryError::Proxy(_) => true,
+            DeliveryError::Timeout(_) | DeliveryError::Task(_) => false,
+        }
+    }
+}
+
+/// Attempt the delivery once and again after every failure that can be retried,
+/// until the retries of the message run out.
+async fn retry_delivery<F, Fut>(retries: u32, mut attempt: F) -> Result<Response, DeliveryError>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<Response, DeliveryError>>,
+{
+    let mut retried = 0;
+
+    loop {
+        match attempt().await {
+            Err(e) if retried < retries && e.is_retryable() => {
+                retried += 1;
+
+                tracing::warn!(
+                    retry = retried,
+                    retries,
+                    "Retrying email in Smtp after: {:?}",
+                    e
+                );
+
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Deliver the message through the transport with the retry policy of its template
+async fn deliver_with_retries(
+    smtp: SmtpTransport,
+    outgoing: Outgoing,
+) -> Result<Response, DeliveryError> {
+    let Outgoing {
+        message,
+        envelope,
+        retries,
+        send_timeout,
+    } = outgoing;
+
+    // Without retries the message is moved to the delivery, there is nothing to clone it for
+    if retries == 0 {
+        return deliver_in_time(smtp, envelope, message, send_timeout).await;
+    }
+
+    retry_delivery(retries, || {
+        deliver_in_time(
+            smtp.clone(),
+            envelope.clone(),
+            message.clone(),
+            send_timeout,
+        )
+    })
+    .await
+}
+
+impl From<DeliveryError> for Error {
+    fn from(e: DeliveryError) -> Self {
+        match e {
+            DeliveryError::Smtp(e) => Error::from(e),
+            DeliveryError::Proxy(e) => Error::from(e),
+            DeliveryError::Timeout(timeout) => Error::EmailTimeout(format!(
+                "smtp_send_timed_out: the relay didn't accept the message in {}s",
+                timeout.as_secs_f32()
+            )),
+            DeliveryError::Task(message) => Error::InternalError(message),
+        }
+    }
+}
+
+/// Deliver the message on a blocking thread and give up waiting for it after the timeout.
+/// The thread can't be interrupted, it finishes once the transport gives up on its own.
+async fn deliver_in_time(
+    smtp: SmtpTransport,
+    envelope: Envelope,
+    message: Message,
+    timeout: Duration,
+) -> Result<Response, DeliveryError> {
+    let span = tracing::Span::current();
+
+    let task = tokio::task::spawn_blocking(move || {
+        // Blocking threads don't inherit the span, without it the
+        // message span would be detached from the batch it belongs to
+        let _entered = span.enter();
+
+        // The pool pings a reused connection with NOOP before MAIL FROM, a connection the
+        // relay closed while it was idle is dropped there and the message goes over a new one.
+        // A connection lost later is not resent, by then the relay may have queued the message.
+        deliver(&smtp, &envelope, &message)
+    });
+
+    match tokio::time::timeout(timeout, task).await {
+        Ok(Ok(result)) => result.map_err(DeliveryError::Smtp),
+        Ok(Err(e)) => Err(DeliveryError::Task(e.to_string())),
+        Err(_) => {
+            tracing::error!(
+                timeout_ms = timeout.as_millis() as u64,
+                "Timed out sending email in Smtp"
+            );
+
+            Err(DeliveryError::Timeout(timeout))
+        }
+    }
+}
+
+/// Check that the relay answers on a blocking thread, the same way the messages are delivered,
+/// a pooled connection is pinged with NOOP and a new one is opened when it doesn't answer.
+async fn test_connection_in_time(
+    smtp: SmtpTransport,
+    timeout: Duration,
+) -> Result<bool, DeliveryError> {
+    let task = tokio::task::spawn_blocking(move || smtp.test_connection());
+
+    match tokio::time::timeout(timeout, task).await {
+        Ok(Ok(result)) => result.map_err(DeliveryError::Smtp),
+        Ok(Err(e)) => Err(DeliveryError::Task(e.to_string())),
+        Err(_) => Err(DeliveryError::Timeout(timeout)),
+    }
+}
+
+/// Error of a connection test, a timeout means the relay didn't answer, there was no message
+fn connection_test_error(e: DeliveryError) -> Error {
+    match e {
+        DeliveryError::Timeout(timeout) => Error::EmailTimeout(format!(
+            "smtp_connection_test_timed_out: the relay didn't answer in {}s",
+            timeout.as_secs_f32()
+        )),
+        e => Error::from(e),
+    }
+}
+
+/// Send a single message through the transport, returns the response of the relay,
+/// the message was accepted only if the response is positive.
+fn deliver(
+    smtp: &SmtpTransport,
+    envelope: &Envelope,
+    message: &Message,
+) -> Result<Response, SmtpError> {
+    let recipients = envelope
+        .to()
+        .iter()
+        .map(|address| address.to_string())
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    let span = tracing::info_span!(
+        "smtp_deliver",
+        recipients = %recipients,
+        subject = message.headers().get_raw("Subject").unwrap_or_default(),
+    );
+    let _entered = span.enter();
+
+    let started = Instant::now();
+    let result = smtp.send_raw(envelope, &message.formatted());
+    let elapsed_ms = started.elapsed().as_millis() as u64;
+
+    match result {
+        Ok(response) => {
+            if response.is_positive() {
+                tracing::info!(code = %response.code(), elapsed_ms, "Email sent in Smtp");
+            } else {
+                tracing::error!(
+                    code = %response.code(),
+                    elapsed_ms,
+                    "Negative response sending email in Smtp: {:?}, message: {:?}",
+                    response,
+                    message
+                );
+            }
+
+            Ok(response)
+        }
+        Err(e) => {
+            tracing::error!(
+                elapsed_ms,
+                "Error sending email in Smtp: {}, message: {:?}",
+                e,
+                message
+            );
+
+            Err(e)
+        }
+    }
+}
+
+/// Deliver the messages one by one through the proxy, each over its own connection
+async fn deliver_through_proxy(
+    proxy: &ProxiedRelay,
+    messages: Vec<Option<Outgoing>>,
+    stop_on_error: bool,
+) -> Vec<AppResult<Option<Response>>> {
+    let mut results = vec![];
+
+    for message in messages {
+        let result = match message {
+            Some(outgoing) => retry_delivery(outgoing.retries, || {
+                deliver_in_time_through_proxy(proxy, &outgoing, outgoing.send_timeout)
+            })
+            .await
+            .map(Some),
+            None => Ok(None),
+        };
+
+        let failed = result.is_err();
+        results.push(result);
+
+        if failed && stop_on_error {
+            break;
+        }
+    }
+
+    results
+        .into_iter()
+        .map(|result| result.map_err(Error::from))
+        .collect()
+}
+
+/// Send the message through the proxy and give up waiting for it after the timeout
+async fn deliver_in_time_through_proxy(
+    proxy: &ProxiedRelay,
+    outgoing: &Outgoing,
+    send_timeout: Duration,
+) -> Result<Response, DeliveryError> {
+    let send = proxy.send(&outgoing.envelope, &outgoing.message);
+
+    match tokio::time::timeout(send_timeout, send).await {
+        Ok(Ok(response)) => Ok(response),
+        Ok(Err(e)) => {
+            tracing::error!("Error sending email in Smtp through the proxy: {:?}", e);
+
+            Err(DeliveryError::Proxy(e))
+        }
+        Err(_) => {
+            tracing::error!("Timed out sending email in Smtp through the proxy");
+
+            Err(DeliveryError::Timeout(send_timeout))
+        }
     }
 }
 
+/// Queue id the relay assigned to the message, most relays report it
+/// in the response to the message body, e.g. `250 2.0.0 Ok: queued as 4F2A1`.
+fn queue_id(response: &Response) -> Option<String> {
+    response.message().find_map(|line| {
+        line.split_once("queued as ")
+            .and_then(|(_, rest)| rest.split_whitespace().next())
+            .map(|id| id.to_string())
+    })
+}
+
+/// Code and message of the relay response, the message lines are joined with spaces
+fn relay_response(response: &Response) -> RelayResponse {
+    let message = response.message().collect::<Vec<_>>().join(" ");
+
+    RelayResponse::new(u16::from(response.code()), message)
+}
+
 #[async_trait::async_trait]
 impl SenderContract for SmtpSender {
+    /// Every template is delivered as one message no matter how many recipients it has,
+    /// so a multi-recipient template counts as one sent message when the relay accepts it.
+    #[tracing::instrument(name = "smtp_send", skip_all, fields(emails = emails.len()))]
     async fn send(&self, emails: Vec<Template>) -> AppResult<usize> {
+        if self.validate_before_batch && !emails.is_empty() {
+            self.validate_connection().await?;
+        }
+
+        let messages = emails
+            .into_iter()
+            .filter(|email| self.missing_recipient(email).is_none())
+            .map(|email| {
+                self.prepare(email)
+                    .inspect_err(|e| self.metrics.record_failed(e.email_error_kind()))
+            })
+            .collect::<AppResult<Vec<_>>>()?;
+
         let mut sent = 0;
 
-        for mut email in emails {
-            if !email.has_from() {
-                email = email.from_mailbox(&self.default_from);
+        for result in self.deliver_all(messages, true).await {
+            self.record(&result);
+
+            match result? {
+                Some(response) if !response.is_positive() => (),
+                _ => sent += 1,
             }
+        }
 
-            if email.skip_send() {
-                sent += 1;
+        Ok(sent)
+    }
 
-                continue;
-            }
+    /// The message id of a sent email is the queue id reported by the relay,
+    /// or the Message-ID header when the relay didn't report one.
+    #[tracing::instrument(name = "smtp_send_outcomes", skip_all, fields(emails = emails.len()))]
+    async fn send_outcomes(&self, emails: Vec<Template>) -> AppResult<Vec<SendOutcome>> {
+        if self.validate_before_batch && !emails.is_empty() {
+            self.validate_connection().await?;
+        }
 
-            let message = email.message()?;
-
-            match self.smtp.send(&message) {
-                Ok(response) => {
-                    if response.is_positive() {
-                        sent += 1;
-                    } else {
-                        log::error!(
-                            "Negative response sending email in Smtp: {:?}, message: {:?}",
-                            response,
-                            message
-                        );
-                    }
-                }
+        let mut prepared = vec![];
+        let mut messages = vec![];
+
+        for email in emails {
+            // Taken before preparing, the archive copy isn't one of the recipients
+            let recipients = email.recipients();
+
+            let prepared_message = match self.missing_recipient(&email) {
+                Some(error) => Err(error),
+                None => self.prepare(email),
+            };
+
+            let (message, error) = match prepared_message {
+                Ok(message) => (message, None),
                 Err(e) => {
-                    log::error!("Error sending email in Smtp: {}, message: {:?}", e, message);
+                    self.metrics.record_failed(e.email_error_kind());
 
-                    return Err(Error::from(e));
+                    (None, Some(e.to_string()))
                 }
-            }
+            };
+
+            let header = message.as_ref().and_then(|outgoing| {
+                outgoing
+                    .message
+                    .headers()
+                    .get_raw("Message-ID")
+                    .map(|id| id.to_string())
+            });
+
+            prepared.push((recipients, header, error));
+            messages.push(message);
         }
 
-        Ok(sent)
-    }
+        let results = self.deliver_all(messages, false).await;
 
-    fn boxed_clone(&self) -> Box<dyn SenderContract> {
-        Box::new(self.clone())
+        let outcomes = prepared
+            .into_iter()
+            .zip(results)
+            .map(|((recipients, header, error), result)| {
+                if let Some(error) = error {
+                    return SendOutcome::failed(recipients, error);
+                }
+
+                self.record(&result);
+
+                match result {
+                    Ok(None) => SendOutcome::sent(recipients, None),
+                    Ok(Some(response)) if response.is_positive() => {
+                        SendOutcome::sent(recipients, queue_id(&response).or(header))
+                            .with_relay_response(Some(relay_response(&response)))
+                    }
+                    Ok(Some(response)) => {
+                        let relay_response = relay_response(&response);
+
+                        SendOutcome::failed(
+                            recipients,
+                            format!("negative_smtp_response: {relay_response}"),
+                        )
+                        .with_relay_response(Some(relay_response))
+                    }
+                    Err(e) => {
+                        let relay_response = e
+                            .smtp_reply()
+                            .map(|(code, message)| RelayResponse::new(code, message));
+
+                        SendOutcome::failed(recipients, e.to_string())
+                            .with_relay_response(relay_response)
+                    }
+                }
+            })
+            .collect();
+
+        Ok(outcomes)
+    }
+
+    /// A negative response of the relay fails with its code and message
+    /// instead of just telling that the email wasn't sent.
+    async fn send_one(&self, email: Template) -> AppResult<()> {
+        if self.validate_before_batch {
+            self.validate_connection().await?;
+        }
+
+        let message = self
+            .prepare(email)
+            .inspect_err(|e| self.metrics.record_failed(e.email_error_kind()))?;
+
+        for result in self.deliver_all(vec![message], true).await {
+            self.record(&result);
+
+            if let Some(response) = result? {
+                if !response.is_positive() {
+                    return Err(Error::InternalError(format!(
+                        "negative_smtp_response: {}",
+                        relay_response(&response)
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "smtp_test_connection", skip_all)]
+    async fn test_connection(&self) -> AppResult<()> {
+        if let Some(proxy) = self.proxy.as_ref() {
+            return proxy.test_connection().await.map_err(Error::from);
+        }
+
+        // The relay is reached on a blocking thread, a slow one doesn't hold up the caller
+        let connected = test_connection_in_time(self.smtp.clone(), self.send_timeout)
+            .await
+            .map_err(|e| match e {
+                DeliveryError::Smtp(e) => {
+                    Self::connection_error(&self.relay.0, self.relay.1, self.min_tls_version, e)
+                }
+                e => connection_test_error(e),
+            })?;
+
+        if connected {
+            Ok(())
+        } else {
+            Err(Error::InternalError(
+                "smtp_connection_test_failed".to_string(),
+            ))
+        }
+    }
+
+    fn capabilities(&self) -> SenderCapabilities {
+        SenderCapabilities {
+            supports_attachments: true,
+            supports_bcc: true,
+            supports_connection_test: true,
+            is_real_delivery: true,
+        }
+    }
+
+    fn boxed_clone(&self) -> Box<dyn SenderContract> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SmtpSender;
+    use crate::contract::SenderContract;
+    use crate::metrics::EmailMetrics;
+    use crate::template::{RetryPolicy, Template};
+    use crate::test_server::{ServerOptions, TestProxy, TestServer};
+    use config::email::{DkimCredentials, MinTlsVersion, SmtpCredentials, SmtpProxy, TlsMode};
+    use error::{EmailErrorKind, Error};
+
+    fn email(to: &str) -> Template {
+        let mut template = Template::new("subject", "pre_header").unwrap();
+        template.register_content_template("content").unwrap();
+
+        template.to(to).unwrap()
+    }
+
+    #[tokio::test]
+    async fn validation_before_batch_reconnects_stale_connection() {
+        let server = TestServer::start_with(ServerOptions {
+            drop_after_message: true,
+            ..Default::default()
+        });
+
+        let mut credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        credentials.validate_before_batch = true;
+
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        // Leaves a pooled connection that the server has already closed
+        assert_eq!(sender.send(vec![email("first@doe.com")]).await.unwrap(), 1);
+
+        let sent = sender
+            .send(vec![email("second@doe.com"), email("third@doe.com")])
+            .await
+            .unwrap();
+
+        assert_eq!(sent, 2);
+        assert_eq!(server.messages().len(), 3);
+        assert!(server.connections() > 1);
+    }
+
+    #[tokio::test]
+    async fn email_without_recipients_is_skipped_and_reported() {
+        let server = TestServer::start_with(ServerOptions::default());
+        let sender = SmtpSender::new(&SmtpCredentials::mock("127.0.0.1", server.port())).unwrap();
+
+        let mut missing = Template::new("No one to send to", "pre_header").unwrap();
+        missing.register_content_template("content").unwrap();
+
+        let emails = vec![
+            email("first@doe.com"),
+            missing.clone(),
+            email("second@doe.com"),
+        ];
+
+        assert_eq!(sender.send(emails).await.unwrap(), 2);
+        assert_eq!(server.messages().len(), 2);
+
+        let emails = vec![email("first@doe.com"), missing.clone()];
+        let outcomes = sender.send_outcomes(emails).await.unwrap();
+
+        assert!(outcomes[0].sent);
+        assert!(!outcomes[1].sent);
+        assert!(outcomes[1].recipients.is_empty());
+        assert!(outcomes[1]
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("email_missing_recipient"));
+        assert_eq!(server.messages().len(), 3);
+
+        assert!(sender.send_one(missing).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn from_outside_the_allowed_domains_is_replaced_or_rejected() {
+        let server = TestServer::start_with(ServerOptions::default());
+
+        let mut credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        credentials.allowed_from_domains = vec!["test.com".to_string()];
+
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        let emails = vec![
+            email("first@doe.com").from("other@test.com").unwrap(),
+            email("second@doe.com").from("spoofed@evil.com").unwrap(),
+        ];
+
+        assert_eq!(sender.send(emails).await.unwrap(), 2);
+
+        let messages = server.messages();
+
+        assert_eq!(messages[0].sender, "other@test.com");
+        assert_eq!(messages[1].sender, "mock@test.com");
+        assert!(!messages[1].data.contains("evil.com"));
+
+        credentials.strict_from_domains = true;
+
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        let error = sender
+            .send_one(email("third@doe.com").from("spoofed@evil.com").unwrap())
+            .await
+            .unwrap_err();
+
+        assert!(error
+            .to_string()
+            .contains("from_domain_not_allowed:evil.com"));
+        assert_eq!(server.messages().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn dropped_pooled_connection_is_replaced_before_sending() {
+        let server = TestServer::start_with(ServerOptions {
+            drop_on_reuse: true,
+            ..Default::default()
+        });
+
+        let sender = SmtpSender::new(&server.credentials()).unwrap();
+        let probed = server.transactions();
+
+        assert_eq!(sender.send(vec![email("first@doe.com")]).await.unwrap(), 1);
+
+        // The pooled connection is dropped on the next message, without any retry policy
+        assert_eq!(sender.send(vec![email("second@doe.com")]).await.unwrap(), 1);
+
+        let messages = server.messages();
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].recipients, vec!["second@doe.com".to_string()]);
+        assert_eq!(server.transactions(), probed + 2);
+        assert!(server.connections() > 1);
+    }
+
+    #[tokio::test]
+    async fn connection_dropped_after_the_message_is_not_resent() {
+        let server = TestServer::start_with(ServerOptions {
+            drop_before_data_response: true,
+            ..Default::default()
+        });
+
+        let sender = SmtpSender::new(&server.credentials()).unwrap();
+        let probed = server.transactions();
+
+        assert!(sender.send(vec![email("first@doe.com")]).await.is_err());
+
+        // The relay got the whole message, sending it again could deliver it twice
+        assert_eq!(server.messages().len(), 1);
+        assert_eq!(server.transactions(), probed + 1);
+    }
+
+    #[tokio::test]
+    async fn concurrent_batch_sends_in_parallel() {
+        let server = TestServer::start_with(ServerOptions {
+            data_delay: Some(std::time::Duration::from_millis(300)),
+            ..Default::default()
+        });
+
+        let mut credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        credentials.concurrency = 4;
+
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        let emails = (0..4)
+            .map(|i| email(format!("{i}@doe.com").as_str()))
+            .collect();
+
+        assert_eq!(sender.send(emails).await.unwrap(), 4);
+        assert!(server.max_concurrent_deliveries() > 1);
+        assert_eq!(server.messages().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn batch_is_split_by_the_max_batch_size() {
+        let server = TestServer::start_with(ServerOptions::default());
+
+        let mut credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        credentials.max_batch_size = Some(2);
+
+        let sender = SmtpSender::new(&credentials).unwrap();
+        let connected = server.connections();
+
+        let emails = (0..5)
+            .map(|i| email(format!("{i}@doe.com").as_str()))
+            .collect();
+
+        assert_eq!(sender.send(emails).await.unwrap(), 5);
+
+        // Every part of at most two messages went over a connection of its own
+        assert_eq!(server.connections(), connected + 3);
+
+        let recipients = server
+            .messages()
+            .iter()
+            .flat_map(|message| message.recipients.clone())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            recipients,
+            (0..5).map(|i| format!("{i}@doe.com")).collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn split_batch_keeps_the_order_of_the_outcomes() {
+        let server = TestServer::start_with(ServerOptions {
+            rejected_recipients: vec!["3@doe.com".to_string()],
+            ..Default::default()
+        });
+
+        let mut credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        credentials.max_batch_size = Some(2);
+        credentials.concurrency = 2;
+
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        let emails = (0..5)
+            .map(|i| email(format!("{i}@doe.com").as_str()))
+            .collect();
+
+        let outcomes = sender.send_outcomes(emails).await.unwrap();
+
+        assert_eq!(outcomes.len(), 5);
+
+        for (i, outcome) in outcomes.iter().enumerate() {
+            assert_eq!(outcome.recipients, vec![format!("{i}@doe.com")]);
+            assert_eq!(outcome.sent, i != 3);
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_batch_reports_failures() {
+        let server = TestServer::start_with(ServerOptions {
+            data_response: "550 5.1.1 Mailbox unavailable".to_string(),
+            ..Default::default()
+        });
+
+        let mut credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        credentials.concurrency = 2;
+
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        let emails = vec![email("first@doe.com"), email("second@doe.com")];
+
+        assert!(sender.send(emails).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn stalled_relay_times_out_as_transient() {
+        let server = TestServer::start_with(ServerOptions {
+            data_delay: Some(std::time::Duration::from_secs(1)),
+            ..Default::default()
+        });
+
+        let mut credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        credentials.send_timeout = std::time::Duration::from_millis(200);
+
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        let started = std::time::Instant::now();
+
+        let error = sender.send(vec![email("first@doe.com")]).await.unwrap_err();
+
+        assert!(started.elapsed() < std::time::Duration::from_millis(800));
+        assert!(matches!(error, Error::EmailTimeout(_)));
+        assert_eq!(error.email_error_kind(), Some(EmailErrorKind::Transient));
+    }
+
+    #[tokio::test]
+    async fn retry_policy_overrides_the_send_timeout() {
+        let server = TestServer::start_with(ServerOptions {
+            data_delay: Some(std::time::Duration::from_secs(1)),
+            ..Default::default()
+        });
+
+        let credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        let policy = RetryPolicy {
+            retries: None,
+            send_timeout: Some(std::time::Duration::from_millis(200)),
+        };
+
+        let started = std::time::Instant::now();
+
+        let error = sender
+            .send(vec![email("first@doe.com").with_retry_policy(policy)])
+            .await
+            .unwrap_err();
+
+        assert!(started.elapsed() < std::time::Duration::from_millis(800));
+        assert!(matches!(error, Error::EmailTimeout(_)));
+    }
+
+    #[tokio::test]
+    async fn retry_policy_doesnt_retry_timeouts() {
+        let server = TestServer::start_with(ServerOptions {
+            data_delay: Some(std::time::Duration::from_secs(1)),
+            ..Default::default()
+        });
+
+        let credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        let sender = SmtpSender::new(&credentials).unwrap();
+        let probed = server.transactions();
+
+        let policy = RetryPolicy {
+            retries: Some(2),
+            send_timeout: Some(std::time::Duration::from_millis(200)),
+        };
+
+        let error = sender
+            .send(vec![email("first@doe.com").with_retry_policy(policy)])
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, Error::EmailTimeout(_)));
+
+        // The stalled attempt still completes on its own, it is the only one
+        tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+
+        assert_eq!(server.transactions(), probed + 1);
+    }
+
+    #[tokio::test]
+    async fn retry_policy_retries_transient_failures() {
+        let server = TestServer::start_with(ServerOptions {
+            data_response: "451 4.7.1 Greylisted, try again later".to_string(),
+            ..Default::default()
+        });
+
+        let credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        // The startup diagnostic probes the relay with a transaction of its own
+        let probed = server.transactions();
+
+        // Without a policy the message is attempted once, the same as before
+        assert!(sender.send(vec![email("first@doe.com")]).await.is_err());
+        assert_eq!(server.transactions(), probed + 1);
+
+        let policy = RetryPolicy {
+            retries: Some(1),
+            send_timeout: None,
+        };
+
+        let error = sender
+            .send(vec![email("first@doe.com").with_retry_policy(policy)])
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.email_error_kind(), Some(EmailErrorKind::Transient));
+        assert_eq!(server.transactions(), probed + 3);
+    }
+
+    #[tokio::test]
+    async fn retry_policy_doesnt_retry_permanent_failures() {
+        let server = TestServer::start_with(ServerOptions {
+            data_response: "550 5.1.1 Mailbox unavailable".to_string(),
+            ..Default::default()
+        });
+
+        let credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        let sender = SmtpSender::new(&credentials).unwrap();
+        let probed = server.transactions();
+
+        let policy = RetryPolicy {
+            retries: Some(3),
+            send_timeout: None,
+        };
+
+        assert!(sender
+            .send(vec![email("first@doe.com").with_retry_policy(policy)])
+            .await
+            .is_err());
+        assert_eq!(server.transactions(), probed + 1);
+    }
+
+    #[test]
+    fn diagnostic_warns_when_relay_ignores_auth() {
+        let server = TestServer::start_with(ServerOptions {
+            advertise_auth: false,
+            ..Default::default()
+        });
+
+        let credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        let warning = SmtpSender::diagnose_authentication(&credentials).unwrap();
+
+        assert!(warning.unwrap().contains("does not advertise AUTH"));
+    }
+
+    #[test]
+    fn diagnostic_warns_when_relay_accepts_unauthenticated_mail() {
+        let server = TestServer::start_with(ServerOptions::default());
+
+        let credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        let warning = SmtpSender::diagnose_authentication(&credentials).unwrap();
+
+        assert!(warning.unwrap().contains("without authentication"));
+    }
+
+    #[test]
+    fn diagnostic_passes_when_relay_only_accepts_its_own_domains() {
+        // The relay takes mail for its own domains without authentication, as their MX
+        let server = TestServer::start_with(ServerOptions {
+            rejected_recipients: vec!["postmaster@example.invalid".to_string()],
+            ..Default::default()
+        });
+
+        let credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        let warning = SmtpSender::diagnose_authentication(&credentials).unwrap();
+
+        assert!(warning.is_none());
+    }
+
+    #[test]
+    fn diagnostic_passes_when_relay_requires_auth() {
+        let server = TestServer::start_with(ServerOptions {
+            require_auth: true,
+            ..Default::default()
+        });
+
+        let credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        let warning = SmtpSender::diagnose_authentication(&credentials).unwrap();
+
+        assert!(warning.is_none());
+    }
+
+    #[test]
+    fn startup_verification_can_be_skipped() {
+        let port = closed_port();
+
+        let mut credentials = SmtpCredentials::mock("127.0.0.1", port);
+        assert!(SmtpSender::new(&credentials).is_err());
+
+        credentials.verify_on_startup = false;
+        assert!(SmtpSender::new(&credentials).is_ok());
+    }
+
+    #[test]
+    fn rejected_credentials_fail_the_startup_with_verify_auth() {
+        let server = TestServer::start_with(ServerOptions {
+            reject_auth: true,
+            ..Default::default()
+        });
+
+        let mut credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        credentials.verify_on_startup = false;
+        assert!(SmtpSender::new(&credentials).is_ok());
+        assert_eq!(server.auth_attempts(), 0);
+
+        credentials.verify_auth = true;
+        let error = SmtpSender::new(&credentials).err().unwrap().to_string();
+
+        assert!(error.contains("SMTP authentication failed for 'mock'"));
+        assert!(error.contains("check SMTP_USERNAME/SMTP_PASSWORD"));
+        assert_eq!(server.auth_attempts(), 1);
+    }
+
+    #[test]
+    fn accepted_credentials_pass_the_startup_with_verify_auth() {
+        let server = TestServer::start_with(ServerOptions::default());
+
+        let mut credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        credentials.verify_on_startup = false;
+        credentials.verify_auth = true;
+
+        assert!(SmtpSender::new(&credentials).is_ok());
+        assert_eq!(server.auth_attempts(), 1);
+    }
+
+    #[tokio::test]
+    async fn multiple_recipients_are_sent_as_one_message() {
+        let server = TestServer::start_with(ServerOptions::default());
+
+        let credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        let template = email("first@doe.com").to("second@doe.com").unwrap();
+
+        assert_eq!(sender.send(vec![template]).await.unwrap(), 1);
+
+        let messages = server.messages();
+
+        assert_eq!(messages.len(), 1);
+        assert_eq!(
+            messages[0].recipients,
+            vec!["first@doe.com", "second@doe.com"]
+        );
+    }
+
+    #[tokio::test]
+    async fn configured_message_id_domain_is_used() {
+        let server = TestServer::start_with(ServerOptions::default());
+
+        let mut credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        credentials.message_id_domain = Some("example.com".to_string());
+
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        assert_eq!(sender.send(vec![email("first@doe.com")]).await.unwrap(), 1);
+
+        let data = &server.messages()[0].data;
+
+        assert!(data
+            .lines()
+            .any(|line| line.starts_with("Message-ID: <") && line.ends_with("@example.com>")));
+    }
+
+    #[tokio::test]
+    async fn bcc_archive_receives_hidden_copy() {
+        let server = TestServer::start_with(ServerOptions::default());
+
+        let mut credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        credentials.bcc_archive = Some("archive@doe.com".to_string());
+
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        assert_eq!(sender.send(vec![email("first@doe.com")]).await.unwrap(), 1);
+
+        let messages = server.messages();
+
+        assert_eq!(messages.len(), 1);
+        assert_eq!(
+            messages[0].recipients,
+            vec!["first@doe.com", "archive@doe.com"]
+        );
+        assert!(!messages[0].data.contains("archive@doe.com"));
+    }
+
+    #[tokio::test]
+    async fn envelope_sender_differs_from_the_from_header() {
+        let server = TestServer::start_with(ServerOptions::default());
+
+        let mut credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        credentials.bcc_archive = Some("archive@doe.com".to_string());
+
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        assert_eq!(sender.send(vec![email("first@doe.com")]).await.unwrap(), 1);
+
+        // Without the envelope sender the envelope follows the From header
+        assert_eq!(server.messages()[0].sender, "mock@test.com");
+
+        credentials.envelope_from = Some("bounces@doe.com".to_string());
+
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        assert_eq!(sender.send(vec![email("first@doe.com")]).await.unwrap(), 1);
+
+        let messages = server.messages();
+
+        assert_eq!(messages[1].sender, "bounces@doe.com");
+        assert_eq!(
+            messages[1].recipients,
+            vec!["first@doe.com", "archive@doe.com"]
+        );
+        assert!(messages[1]
+            .data
+            .contains("From: \"Mock Test\" <mock@test.com>"));
+    }
+
+    #[tokio::test]
+    async fn configured_hello_name_is_announced() {
+        let server = TestServer::start_with(ServerOptions::default());
+
+        let mut credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        credentials.hello_name = Some("mail.example.com".to_string());
+
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        assert_eq!(sender.send(vec![email("first@doe.com")]).await.unwrap(), 1);
+
+        let hello_names = server.hello_names();
+
+        assert!(!hello_names.is_empty());
+        assert!(hello_names.iter().all(|name| name == "mail.example.com"));
+    }
+
+    #[tokio::test]
+    async fn blank_username_connects_without_credentials() {
+        let server = TestServer::start_with(ServerOptions::default());
+
+        let mut credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        credentials.username = String::new();
+        credentials.password = String::new();
+
+        assert!(SmtpSender::credentials(&credentials).is_none());
+
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        assert_eq!(sender.send(vec![email("first@doe.com")]).await.unwrap(), 1);
+        assert_eq!(server.messages().len(), 1);
+        assert_eq!(server.auth_attempts(), 0);
+    }
+
+    #[tokio::test]
+    async fn configured_username_authenticates() {
+        let server = TestServer::start_with(ServerOptions::default());
+
+        let credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+
+        assert!(SmtpSender::credentials(&credentials).is_some());
+
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        assert_eq!(sender.send(vec![email("first@doe.com")]).await.unwrap(), 1);
+        assert!(server.auth_attempts() > 0);
+    }
+
+    async fn send_error_kind(data_response: &str) -> Option<EmailErrorKind> {
+        let server = TestServer::start_with(ServerOptions {
+            data_response: data_response.to_string(),
+            ..Default::default()
+        });
+
+        let credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        let error = sender.send(vec![email("first@doe.com")]).await.unwrap_err();
+
+        error.email_error_kind()
+    }
+
+    #[tokio::test]
+    async fn greylisting_is_classified_as_transient() {
+        let kind = send_error_kind("451 4.7.1 Greylisted, try again later").await;
+
+        assert_eq!(kind, Some(EmailErrorKind::Transient));
+        assert!(kind.unwrap().is_retryable());
+    }
+
+    #[tokio::test]
+    async fn rejected_mailbox_is_classified_as_permanent() {
+        let kind = send_error_kind("550 5.1.1 Mailbox unavailable").await;
+
+        assert_eq!(kind, Some(EmailErrorKind::Permanent));
+        assert!(!kind.unwrap().is_retryable());
+    }
+
+    #[tokio::test]
+    async fn rejection_carries_the_relay_reply() {
+        let server = TestServer::start_with(ServerOptions {
+            data_response: "550 5.1.1 Mailbox unavailable".to_string(),
+            ..Default::default()
+        });
+
+        let credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        let error = sender.send_one(email("first@doe.com")).await.unwrap_err();
+
+        assert_eq!(
+            error.smtp_reply(),
+            Some((550, "5.1.1 Mailbox unavailable".to_string()))
+        );
+        assert_eq!(Error::NotFound("x".to_string()).smtp_reply(), None);
+    }
+
+    #[tokio::test]
+    async fn unreachable_server_is_classified_as_connection() {
+        let port = closed_port();
+
+        let mut credentials = SmtpCredentials::mock("127.0.0.1", port);
+        credentials.verify_on_startup = false;
+
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        let error = sender.send(vec![email("first@doe.com")]).await.unwrap_err();
+
+        assert_eq!(error.email_error_kind(), Some(EmailErrorKind::Connection));
+    }
+
+    #[tokio::test]
+    async fn invalid_message_is_classified_as_permanent() {
+        let server = TestServer::start_with(ServerOptions::default());
+
+        let credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        // Missing recipient can never be sent
+        let template = Template::new("subject", "pre_header").unwrap();
+        let error = sender.send_one(template).await.unwrap_err();
+
+        assert_eq!(error.email_error_kind(), Some(EmailErrorKind::Permanent));
+        assert_eq!(Error::NotFound("x".to_string()).email_error_kind(), None);
+    }
+
+    #[tokio::test]
+    async fn outcomes_carry_relay_queue_id() {
+        let server = TestServer::start_with(ServerOptions {
+            data_response: "250 2.0.0 Ok: queued as 4F2A1".to_string(),
+            ..Default::default()
+        });
+
+        let mut credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        credentials.bcc_archive = Some("archive@doe.com".to_string());
+
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        let outcomes = sender
+            .send_outcomes(vec![email("first@doe.com")])
+            .await
+            .unwrap();
+
+        assert_eq!(outcomes.len(), 1);
+        assert!(outcomes[0].sent);
+        assert_eq!(outcomes[0].message_id.as_deref(), Some("4F2A1"));
+        assert_eq!(outcomes[0].recipients, vec!["first@doe.com"]);
+    }
+
+    #[tokio::test]
+    async fn outcomes_fall_back_to_message_id_header() {
+        let server = TestServer::start_with(ServerOptions::default());
+
+        let mut credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        credentials.message_id_domain = Some("mail.doe.com".to_string());
+
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        let outcomes = sender
+            .send_outcomes(vec![email("first@doe.com")])
+            .await
+            .unwrap();
+
+        let message_id = outcomes[0].message_id.as_deref().unwrap();
+
+        assert!(message_id.ends_with("@mail.doe.com>"));
+    }
+
+    #[tokio::test]
+    async fn outcomes_report_failures_without_failing_the_batch() {
+        let server = TestServer::start_with(ServerOptions {
+            data_response: "550 5.1.1 Mailbox unavailable".to_string(),
+            ..Default::default()
+        });
+
+        let credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        let emails = vec![
+            email("first@doe.com"),
+            Template::new("subject", "pre_header").unwrap(),
+        ];
+
+        let outcomes = sender.send_outcomes(emails).await.unwrap();
+
+        assert_eq!(outcomes.len(), 2);
+        assert!(outcomes.iter().all(|outcome| !outcome.sent));
+        assert!(outcomes.iter().all(|outcome| outcome.error.is_some()));
+
+        let relay_response = outcomes[0].relay_response.as_ref().unwrap();
+
+        assert_eq!(relay_response.code, 550);
+        assert_eq!(relay_response.to_string(), "550 5.1.1 Mailbox unavailable");
+        assert_eq!(outcomes[1].relay_response, None);
+    }
+
+    #[tokio::test]
+    async fn sent_and_failed_emails_are_counted() {
+        let server = TestServer::start_with(ServerOptions::default());
+
+        let credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        let mut sender = SmtpSender::new(&credentials).unwrap();
+        sender.metrics = Box::leak(Box::new(EmailMetrics::default()));
+
+        sender.send(vec![email("first@doe.com")]).await.unwrap();
+
+        // Skipped for the missing recipient, but still counted as failed
+        let template = Template::new("subject", "pre_header").unwrap();
+        assert_eq!(sender.send(vec![template]).await.unwrap(), 0);
+
+        let snapshot = sender.metrics.snapshot();
+
+        assert_eq!(snapshot.sent_total, 1);
+        assert_eq!(snapshot.failed_total.permanent, 1);
+        assert_eq!(snapshot.failed_total.connection, 0);
+    }
+
+    #[tokio::test]
+    async fn configured_dkim_key_signs_messages() {
+        let server = TestServer::start_with(ServerOptions::default());
+
+        let credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        sender.send(vec![email("first@doe.com")]).await.unwrap();
+
+        let mut credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        credentials.dkim = Some(DkimCredentials {
+            domain: "doe.com".to_string(),
+            selector: "hoodik".to_string(),
+            private_key: "nWGxne/9WmC6hEr0kuwsxERJxWl7MmkZcDusAxyuf2A=".to_string(),
+        });
+
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        sender.send(vec![email("second@doe.com")]).await.unwrap();
+
+        let messages = server.messages();
+
+        assert!(!messages[0].data.contains("DKIM-Signature"));
+        assert!(messages[1]
+            .data
+            .contains("DKIM-Signature: v=1; a=ed25519-sha256;"));
+        assert!(messages[1].data.contains("d=doe.com"));
+        assert!(messages[1].data.contains("s=hoodik"));
+    }
+
+    #[tokio::test]
+    async fn display_name_replaces_default_from_name() {
+        let server = TestServer::start_with(ServerOptions::default());
+
+        let credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        let shared = email("first@doe.com").from_display_name("Alice via Hoodik");
+        let own_from = email("second@doe.com")
+            .from("Bob <bob@doe.com>")
+            .unwrap()
+            .from_display_name("Alice via Hoodik");
+
+        sender.send(vec![shared, own_from]).await.unwrap();
+
+        let messages = server.messages();
+
+        assert!(messages[0]
+            .data
+            .contains("From: \"Alice via Hoodik\" <mock@test.com>"));
+        assert!(messages[1].data.contains("From: Bob <bob@doe.com>"));
+    }
+
+    #[tokio::test]
+    async fn unicode_default_from_name_is_encoded() {
+        let server = TestServer::start_with(ServerOptions::default());
+
+        let mut credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        credentials.default_from = "\"Über, Hoodik \\\"Support\\\"\" <support@doe.com>".to_string();
+
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        sender.send(vec![email("first@doe.com")]).await.unwrap();
+
+        let data = &server.messages()[0].data;
+        let from = data
+            .lines()
+            .find(|line| line.starts_with("From: "))
+            .unwrap();
+
+        // RFC 2047 encoded word of the whole name, quotes and the comma included
+        assert_eq!(
+            from,
+            "From: =?utf-8?b?w5xiZXIsIEhvb2RpayAiU3VwcG9ydCI=?= <support@doe.com>"
+        );
+    }
+
+    #[test]
+    fn min_tls_version_builds_tls_parameters() {
+        let mut credentials = SmtpCredentials::mock("smtp.example.com", 587);
+        credentials.min_tls_version = MinTlsVersion::Tls12;
+
+        assert!(SmtpSender::tls_parameters(&credentials).is_ok());
+    }
+
+    #[test]
+    fn tls_is_validated_for_the_sni_host() {
+        let mut credentials = SmtpCredentials::mock("10.0.0.25", 465);
+        credentials.tls_mode = TlsMode::ImplicitTls;
+
+        assert_eq!(SmtpSender::tls_domain(&credentials), "10.0.0.25");
+
+        credentials.tls_sni_host = Some("smtp.example.com".to_string());
+
+        assert_eq!(SmtpSender::tls_domain(&credentials), "smtp.example.com");
+        assert_eq!(
+            SmtpSender::tls_parameters(&credentials).unwrap().domain(),
+            "smtp.example.com"
+        );
+    }
+
+    /// Grab a free port and release it so nothing is listening there
+    fn closed_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    #[test]
+    fn unresolvable_host_is_told_apart_from_refused_connection() {
+        let credentials = SmtpCredentials::mock("smtp.hoodik.invalid", 2525);
+
+        let error = SmtpSender::new(&credentials).err().unwrap();
+
+        assert_eq!(
+            error,
+            Error::SmtpConnection(
+                "Could not resolve SMTP host 'smtp.hoodik.invalid'; check SMTP_ADDRESS".to_string()
+            )
+        );
+        assert_eq!(error.email_error_kind(), Some(EmailErrorKind::Connection));
+
+        let port = closed_port();
+        let credentials = SmtpCredentials::mock("127.0.0.1", port);
+
+        let error = SmtpSender::new(&credentials).err().unwrap();
+
+        assert_eq!(
+            error,
+            Error::SmtpConnection(format!(
+                "SMTP relay 127.0.0.1:{port} refused the connection; check SMTP_PORT"
+            ))
+        );
+    }
+
+    #[test]
+    fn tls_handshake_failures_are_categorized() {
+        let port = TestServer::start_with(ServerOptions::default()).port();
+
+        // The test server only speaks plain SMTP
+        let mut credentials = SmtpCredentials::mock("127.0.0.1", port);
+        credentials.tls_mode = TlsMode::ImplicitTls;
+
+        let error = SmtpSender::new(&credentials).err().unwrap();
+        let message = format!("{error:?}");
+
+        assert!(message.contains(&format!(
+            "TLS handshake failed (relay doesn't speak TLS) connecting to 127.0.0.1:{port} with TLS 1.2 or newer"
+        )), "{message}");
+
+        let failures = [
+            (
+                "SSL routines:tls_post_process_server_certificate:certificate verify failed",
+                "certificate validation",
+            ),
+            (
+                "SSL routines:ssl3_read_bytes:tlsv1 alert protocol version",
+                "protocol version",
+            ),
+            (
+                "SSL routines:ssl3_read_bytes:sslv3 alert handshake failure",
+                "cipher mismatch",
+            ),
+            ("SSL routines:unexpected eof while reading", "handshake"),
+        ];
+
+        for (message, failure) in failures {
+            assert_eq!(super::tls_failure(message).0, failure);
+        }
+    }
+
+    fn proxy(port: u16) -> SmtpProxy {
+        SmtpProxy {
+            host: "127.0.0.1".to_string(),
+            port,
+            username: None,
+            password: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn messages_are_sent_through_proxy() {
+        let server = TestServer::start_with(ServerOptions::default());
+        let proxy_server = TestProxy::start();
+
+        let mut credentials = SmtpCredentials::mock("127.0.0.1", server.port());
+        credentials.proxy = Some(proxy(proxy_server.port()));
+
+        let sender = SmtpSender::new(&credentials).unwrap();
+
+        // Nothing goes directly to the relay
+        assert_eq!(server.connections(), 0);
+
+        assert_eq!(sender.send(vec![email("first@doe.com")]).await.unwrap(), 1);
+        assert!(sender.test_connection().await.is_ok());
+
+        assert_eq!(server.messages().len(), 1);
+        assert_eq!(server.auth_attempts(), 2);
+        assert_eq!(
+            proxy_server.requests(),
+            vec![format!("127.0.0.1:{}", server.port()); 2]
+        );
+    }
+
+    #[tokio::test]
+    async fn unreachable_proxy_is_told_apart_from_unreachable_relay() {
+        let proxy_server = TestProxy::start();
+
+        let mut credentials = SmtpCredentials::mock("127.0.0.1", closed_port());
+        credentials.proxy = Some(proxy(closed_port()));
+
+        let sender = SmtpSender::new(&credentials).unwrap();
+        let error = sender.send(vec![email("first@doe.com")]).await.unwrap_err();
+
+        assert!(error.to_string().contains("smtp_proxy_unreachable"));
+
+        credentials.proxy = Some(proxy(proxy_server.port()));
+
+        let sender = SmtpSender::new(&credentials).unwrap();
+        let error = sender.send(vec![email("first@doe.com")]).await.unwrap_err();
+
+        assert!(error
+            .to_string()
+            .contains("smtp_relay_unreachable_through_proxy"));
     }
 }
@@ -0,0 +1,273 @@
+//! Sender that doesn't talk SMTP at all, every rendered email is posted as JSON to
+//! the configured endpoint, e.g. an external delivery platform or a central log.
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::contract::{SenderCapabilities, SenderContract};
+use crate::metrics::EmailMetrics;
+use crate::template::{InlineImage, Template};
+use config::email::WebhookConfig;
+use error::{AppResult, Error};
+use lettre::message::Mailbox;
+use serde::Serialize;
+
+/// Time allowed for the endpoint to accept a single email
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Body of the request posted for every email
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookPayload {
+    pub to: Vec<String>,
+    pub bcc: Vec<String>,
+    pub from: Option<String>,
+    pub reply_to: Option<String>,
+    pub subject: String,
+    pub html: String,
+    /// Images the HTML references with `cid:`
+    pub inline_images: Vec<InlineImage>,
+}
+
+impl WebhookPayload {
+    /// Render the template into the payload, it fails the same way building
+    /// the email message does when the template has no recipient. The from
+    /// follows the SMTP rules, templates without their own are sent from the default.
+    pub fn from_template(template: &Template, default_from: &Mailbox) -> AppResult<Self> {
+        if !template.has_to() {
+            return Err(Error::BadRequest("email_missing_recipient".to_string()));
+        }
+
+        let template = template.clone().default_from(default_from);
+        let from = template.from_address().map(|mailbox| mailbox.to_string());
+        let source = template.payload();
+
+        Ok(Self {
+            to: source.to,
+            bcc: source.bcc,
+            from,
+            reply_to: source.reply_to,
+            subject: source.subject,
+            html: template.render_html()?,
+            inline_images: source.inline_images,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct WebhookSender {
+    client: reqwest::Client,
+    url: String,
+    auth_header: Option<String>,
+    default_from: Mailbox,
+    metrics: &'static EmailMetrics,
+}
+
+impl WebhookSender {
+    pub fn new(config: &WebhookConfig) -> AppResult<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()?;
+
+        Ok(Self {
+            client,
+            url: config.url.clone(),
+            auth_header: config.auth_header.clone(),
+            default_from: Mailbox::from_str(&config.default_from)?,
+            metrics: EmailMetrics::global(),
+        })
+    }
+
+    /// Post a single email, anything but a 2xx response is a failure
+    async fn post(&self, payload: &WebhookPayload) -> AppResult<()> {
+        let mut request = self.client.post(&self.url).json(payload);
+
+        if let Some(auth_header) = self.auth_header.as_deref() {
+            request = request.header(reqwest::header::AUTHORIZATION, auth_header);
+        }
+
+        request.send().await?.error_for_status()?;
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl SenderContract for WebhookSender {
+    /// Every template is posted in its own request, the first email the endpoint
+    /// doesn't accept fails the batch the same way a rejected SMTP message does.
+    #[tracing::instrument(name = "webhook_send", skip_all, fields(emails = emails.len()))]
+    async fn send(&self, emails: Vec<Template>) -> AppResult<usize> {
+        let mut sent = 0;
+
+        for email in emails {
+            if email.skip_send() {
+                sent += 1;
+                continue;
+            }
+
+            let payload = WebhookPayload::from_template(&email, &self.default_from)
+                .inspect_err(|e| self.metrics.record_failed(e.email_error_kind()))?;
+
+            match self.post(&payload).await {
+                Ok(()) => {
+                    self.metrics.record_sent();
+                    sent += 1;
+                }
+                Err(e) => {
+                    self.metrics.record_failed(e.email_error_kind());
+
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(sent)
+    }
+
+    /// The payload only carries the inline images of the HTML, the calendar attachments
+    /// are left out, and the endpoint can't be probed without posting an email
+    fn capabilities(&self) -> SenderCapabilities {
+        SenderCapabilities {
+            supports_attachments: false,
+            supports_bcc: true,
+            supports_connection_test: false,
+            is_real_delivery: true,
+        }
+    }
+
+    fn boxed_clone(&self) -> Box<dyn SenderContract> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::WebhookSender;
+    use crate::contract::SenderContract;
+    use crate::template::Template;
+    use config::email::WebhookConfig;
+    use error::EmailErrorKind;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// Request captured by the test endpoint
+    #[derive(Debug, Clone, Default)]
+    struct Captured {
+        authorization: Option<String>,
+        body: serde_json::Value,
+    }
+
+    /// Start an HTTP endpoint on a random local port that answers every request with the status
+    fn endpoint(status: u16) -> (String, Arc<Mutex<Vec<Captured>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let captured = Arc::new(Mutex::new(vec![]));
+        let requests = captured.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = Captured::default();
+                let mut length = 0;
+
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+
+                    let line = line.trim_end();
+
+                    if line.is_empty() {
+                        break;
+                    }
+
+                    if let Some((name, value)) = line.split_once(": ") {
+                        match name.to_ascii_lowercase().as_str() {
+                            "content-length" => length = value.parse().unwrap(),
+                            "authorization" => request.authorization = Some(value.to_string()),
+                            _ => (),
+                        }
+                    }
+                }
+
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                request.body = serde_json::from_slice(&body).unwrap();
+                requests.lock().unwrap().push(request);
+
+                write!(
+                    stream,
+                    "HTTP/1.1 {status} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                )
+                .unwrap();
+            }
+        });
+
+        (format!("http://127.0.0.1:{port}/hooks/email"), captured)
+    }
+
+    fn template(to: &str) -> Template {
+        let mut template = Template::new("Webhook subject", "pre_header").unwrap();
+        template
+            .register_content_template("<p>Webhook content</p>")
+            .unwrap();
+
+        template.from_display_name("Sender").to(to).unwrap()
+    }
+
+    #[tokio::test]
+    async fn every_email_is_posted_to_the_webhook() {
+        let (url, captured) = endpoint(202);
+
+        let mut config = WebhookConfig::mock(&url);
+        config.auth_header = Some("Bearer secret".to_string());
+
+        let sender = WebhookSender::new(&config).unwrap();
+
+        let emails = vec![
+            template("first@doe.com"),
+            template("second@doe.com").from("sender@doe.com").unwrap(),
+        ];
+
+        assert_eq!(sender.send(emails).await.unwrap(), 2);
+
+        let captured = captured.lock().unwrap().clone();
+
+        assert_eq!(captured.len(), 2);
+        assert_eq!(captured[0].authorization.as_deref(), Some("Bearer secret"));
+        assert_eq!(captured[0].body["to"][0], "first@doe.com");
+        assert_eq!(captured[1].body["to"][0], "second@doe.com");
+        // The display name goes with the default from, the own from of the template is kept
+        assert_eq!(captured[0].body["from"], "Sender <mock@test.com>");
+        assert_eq!(captured[1].body["from"], "sender@doe.com");
+        assert_eq!(captured[0].body["subject"], "Webhook subject");
+        assert!(captured[0].body["html"]
+            .as_str()
+            .unwrap()
+            .contains("<p>Webhook content</p>"));
+    }
+
+    #[tokio::test]
+    async fn responses_other_than_2xx_fail_the_batch() {
+        let (url, captured) = endpoint(503);
+        let sender = WebhookSender::new(&WebhookConfig::mock(&url)).unwrap();
+
+        let emails = vec![template("first@doe.com"), template("second@doe.com")];
+        let error = sender.send(emails).await.unwrap_err();
+
+        assert_eq!(error.email_error_kind(), Some(EmailErrorKind::Transient));
+        assert_eq!(captured.lock().unwrap().len(), 1);
+
+        let (url, _) = endpoint(400);
+        let sender = WebhookSender::new(&WebhookConfig::mock(&url)).unwrap();
+        let error = sender
+            .send(vec![template("first@doe.com")])
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.email_error_kind(), Some(EmailErrorKind::Permanent));
+    }
+}
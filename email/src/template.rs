@@ -1,14 +1,175 @@
+use crate::cache::TemplateCache;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use chrono::{DateTime, FixedOffset, Utc};
+use config::email::TransferEncoding;
 use error::{AppResult, Error};
-use handlebars::Handlebars;
+use handlebars::{html_escape, no_escape, Handlebars};
 use lettre::{
-    message::{header::ContentType, Mailbox, MessageBuilder},
+    message::{
+        header::{ContentTransferEncoding, ContentType, HeaderName, HeaderValue},
+        Attachment, Body, Mailbox, MessageBuilder, MultiPart, SinglePart,
+    },
     Message,
 };
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+/// Default cap of recipients put on a single announcement message,
+/// relays commonly reject messages with too many recipients.
+pub const ANNOUNCEMENT_MAX_RECIPIENTS: usize = 50;
+
+/// Kind of the email, it decides if the recipients preferences are respected
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailCategory {
+    /// Emails the user needs no matter what, e.g. activation or password reset,
+    /// they are always sent.
+    #[default]
+    Transactional,
+    /// Emails the user can turn off, e.g. a file was shared with them
+    Notification,
+}
+
+/// How hard the sender tries to deliver the email, unset values fall back to the
+/// defaults of the sender, e.g. `SMTP_SEND_TIMEOUT_SECONDS` and no retries.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// Extra attempts after a failure that can be retried, e.g. a dropped connection
+    pub retries: Option<u32>,
+    /// Time the relay has to accept the email on every attempt
+    pub send_timeout: Option<Duration>,
+}
+
+impl RetryPolicy {
+    pub fn new(retries: u32, send_timeout: Duration) -> Self {
+        Self {
+            retries: Some(retries),
+            send_timeout: Some(send_timeout),
+        }
+    }
+}
+
+/// Priority of the email, mail clients highlight the important ones
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailPriority {
+    /// Security alerts, account lockouts and the like
+    High,
+    Normal,
+    Low,
+}
+
+impl EmailPriority {
+    /// Values of the `X-Priority`, `Importance` and `Priority` headers,
+    /// clients read different ones so all of them are sent
+    fn headers(&self) -> [(&'static str, &'static str); 3] {
+        match self {
+            EmailPriority::High => [
+                ("X-Priority", "1 (Highest)"),
+                ("Importance", "high"),
+                ("Priority", "urgent"),
+            ],
+            EmailPriority::Normal => [
+                ("X-Priority", "3 (Normal)"),
+                ("Importance", "normal"),
+                ("Priority", "normal"),
+            ],
+            EmailPriority::Low => [
+                ("X-Priority", "5 (Lowest)"),
+                ("Importance", "low"),
+                ("Priority", "non-urgent"),
+            ],
+        }
+    }
+}
+
+/// Recipient of a personalized batch with the variables only its email gets,
+/// see `Template::personalize`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Personalization {
+    pub to: String,
+    /// Variables of the recipient, they are HTML-escaped like `add_template_var`
+    pub vars: BTreeMap<String, String>,
+}
+
+impl Personalization {
+    pub fn new(to: &str) -> Self {
+        Self {
+            to: to.to_string(),
+            vars: BTreeMap::new(),
+        }
+    }
+
+    /// Add a variable only this recipient gets, it overrides the one of the template
+    pub fn var<T: ToString>(mut self, key: &str, value: T) -> Self {
+        self.vars.insert(key.to_string(), value.to_string());
+        self
+    }
+}
+
+/// Image sent along with the HTML, the HTML shows it with `<img src="cid:{cid}">`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InlineImage {
+    pub cid: String,
+    pub content_type: String,
+    /// Base64 encoded content of the image
+    pub content: String,
+}
+
+impl InlineImage {
+    /// MIME part of the image that is related to the HTML
+    fn part(&self) -> AppResult<SinglePart> {
+        let content_type = ContentType::parse(&self.content_type)
+            .map_err(|e| Error::BadRequest(format!("invalid_inline_image_provided:{e}")))?;
+
+        let bytes = STANDARD.decode(&self.content)?;
+
+        Ok(Attachment::new_inline(self.cid.clone()).body(bytes, content_type))
+    }
+}
+
+/// Everything needed to rebuild a template, this is what gets stored
+/// when an email is queued instead of being sent right away.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TemplatePayload {
+    pub subject: String,
+    pub pre_header: String,
+    pub data: BTreeMap<String, Value>,
+    pub content: Option<String>,
+    pub extra_head: Option<String>,
+    pub from: Option<String>,
+    pub from_display_name: Option<String>,
+    pub reply_to: Option<String>,
+    pub to: Vec<String>,
+    pub bcc: Vec<String>,
+    pub expires_at: Option<i64>,
+    pub list_unsubscribe: Option<String>,
+    #[serde(default)]
+    pub category: EmailCategory,
+    pub calendar: Option<String>,
+    #[serde(default)]
+    pub strict: bool,
+    #[serde(default)]
+    pub inline_images: Vec<InlineImage>,
+    pub priority: Option<EmailPriority>,
+    #[serde(default)]
+    pub retry_policy: RetryPolicy,
+    pub suppress_auto_replies: Option<bool>,
+    pub transfer_encoding: Option<TransferEncoding>,
+    pub locale: Option<String>,
+    /// Date header as a unix timestamp, the time the message is built when not set
+    pub date: Option<i64>,
+    /// Offset from UTC in seconds
+    pub timezone: Option<i32>,
+}
+
+#[derive(Clone)]
 pub struct Template {
     pub base: Handlebars<'static>,
-    pub data: BTreeMap<String, String>,
+    pub data: BTreeMap<String, Value>,
     has_to: bool,
     has_from: bool,
     base_content: bool,
@@ -16,20 +177,36 @@ pub struct Template {
     base_extra_head: bool,
     skip_send: bool,
     builder: MessageBuilder,
+    source: TemplatePayload,
+    message_id_domain: Option<String>,
+    template_dir: Option<PathBuf>,
+    cache: &'static TemplateCache,
 }
 
 impl Template {
     /// Create base template with inserted base HTML for the email
     /// Provide it with subject (for the title) and pre_header (for the text that will display as preview)
     pub fn new(subject: &str, pre_header: &str) -> AppResult<Self> {
+        let cache = TemplateCache::global();
+
         let mut base = Handlebars::new();
-        base.register_template_string("__base_template", include_str!("../assets/base.hbs"))?;
+        base.register_template(
+            "__base_template",
+            cache.get("__base_template", include_str!("../assets/base.hbs"))?,
+        );
+
+        // Values are escaped when they are added to the template data, so the
+        // renderer must not escape them again, see `add_raw_template_var`.
+        base.register_escape_fn(no_escape);
 
         let mut data = BTreeMap::new();
-        data.insert("base_subject".to_string(), subject.to_string());
-        data.insert("base_pre_header".to_string(), pre_header.to_string());
-        data.insert("base_app_name".to_string(), "Hoodik".to_string());
-        data.insert("base_app_version".to_string(), "unknown".to_string());
+        data.insert("base_subject".to_string(), html_escape(subject).into());
+        data.insert(
+            "base_pre_header".to_string(),
+            html_escape(pre_header).into(),
+        );
+        data.insert("base_app_name".to_string(), "Hoodik".into());
+        data.insert("base_app_version".to_string(), "unknown".into());
 
         let builder = Message::builder().subject(subject);
 
@@ -43,20 +220,153 @@ impl Template {
             base_extra_head: false,
             skip_send: false,
             builder,
+            source: TemplatePayload {
+                subject: subject.to_string(),
+                pre_header: pre_header.to_string(),
+                ..Default::default()
+            },
+            message_id_domain: None,
+            template_dir: None,
+            cache,
         })
     }
 
-    /// Add any kind of variable to the template
+    /// Rebuild the template from a payload created with `Template::payload`
+    pub fn from_payload(payload: TemplatePayload) -> AppResult<Self> {
+        let mut template = Self::new(&payload.subject, &payload.pre_header)?;
+
+        if let Some(content) = payload.content.as_deref() {
+            template.register_content_template(content)?;
+        }
+
+        if let Some(extra_head) = payload.extra_head.as_deref() {
+            template.register_extra_head_template(extra_head)?;
+        }
+
+        if let Some(from) = payload.from.as_deref() {
+            template = template.from(from)?;
+        }
+
+        if let Some(name) = payload.from_display_name.as_deref() {
+            template = template.from_display_name(name);
+        }
+
+        if let Some(reply_to) = payload.reply_to.as_deref() {
+            template = template.reply_to(reply_to)?;
+        }
+
+        for to in payload.to.iter() {
+            template = template.to(to)?;
+        }
+
+        for bcc in payload.bcc.iter() {
+            template = template.bcc(bcc)?;
+        }
+
+        if let Some(expires_at) = payload.expires_at {
+            let expires_at = DateTime::from_timestamp(expires_at, 0).ok_or_else(|| {
+                Error::BadRequest(format!("invalid_expires_at_provided:{expires_at}"))
+            })?;
+
+            template = template.expires_at(expires_at);
+        }
+
+        if let Some(date) = payload.date {
+            let date = DateTime::from_timestamp(date, 0)
+                .ok_or_else(|| Error::BadRequest(format!("invalid_date_provided:{date}")))?;
+
+            template.set_date(date);
+        }
+
+        if let Some(list_unsubscribe) = payload.list_unsubscribe.as_deref() {
+            template.set_list_unsubscribe(list_unsubscribe)?;
+        }
+
+        if let Some(calendar) = payload.calendar.as_deref() {
+            template.attach_calendar(calendar)?;
+        }
+
+        // Images were validated when they were embedded
+        template.source.inline_images = payload.inline_images;
+
+        if let Some(priority) = payload.priority {
+            template.set_priority(priority);
+        }
+
+        if let Some(suppress) = payload.suppress_auto_replies {
+            template = template.suppress_auto_replies(suppress);
+        }
+
+        if let Some(encoding) = payload.transfer_encoding {
+            template.set_transfer_encoding(encoding);
+        }
+
+        if let Some(locale) = payload.locale.as_deref() {
+            template.set_locale(locale)?;
+        }
+
+        if let Some(timezone) = payload.timezone {
+            let timezone = FixedOffset::east_opt(timezone).ok_or_else(|| {
+                Error::BadRequest(format!("invalid_timezone_provided:{timezone}"))
+            })?;
+
+            template.set_timezone(timezone);
+        }
+
+        template = template
+            .category(payload.category)
+            .strict(payload.strict)
+            .with_retry_policy(payload.retry_policy);
+
+        // Data is already escaped, so it is taken over as is
+        template.data = payload.data;
+
+        Ok(template)
+    }
+
+    /// Extract the payload of the template so it can be stored and rebuilt later
+    pub fn payload(&self) -> TemplatePayload {
+        TemplatePayload {
+            data: self.data.clone(),
+            ..self.source.clone()
+        }
+    }
+
+    /// Add any kind of variable to the template, the value is HTML-escaped
+    /// so it is safe to use with user provided content.
     pub fn add_template_var<T: ToString>(&mut self, key: &str, value: T) {
-        self.data.insert(key.to_string(), value.to_string());
+        self.data
+            .insert(key.to_string(), html_escape(&value.to_string()).into());
+    }
+
+    /// Add a variable to the template without escaping it, use this only when
+    /// the value is markup that is intentionally inserted into the email.
+    pub fn add_raw_template_var<T: ToString>(&mut self, key: &str, value: T) {
+        self.data.insert(key.to_string(), value.to_string().into());
+    }
+
+    /// Add a list or struct shaped variable, e.g. the items of a digest, to use with
+    /// `{{#each}}` and `{{#if}}`. Every string in it is HTML-escaped the same way
+    /// as with `add_template_var`, numbers and booleans are kept as they are.
+    pub fn add_template_data<T: Serialize>(&mut self, key: &str, value: &T) -> AppResult<()> {
+        let mut value = serde_json::to_value(value)?;
+
+        escape_strings(&mut value);
+
+        self.data.insert(key.to_string(), value);
+
+        Ok(())
     }
 
     /// Add additional `<head>` data into the base email template html
     pub fn register_extra_head_template(&mut self, content: &str) -> AppResult<()> {
-        self.base
-            .register_template_string("__base_extra_head", content)?;
+        self.base.register_template(
+            "__base_extra_head",
+            self.cache.get("__base_extra_head", content)?,
+        );
 
         self.base_extra_head = true;
+        self.source.extra_head = Some(content.to_string());
 
         Ok(())
     }
@@ -67,69 +377,176 @@ impl Template {
     /// that has access to all the data provided through the add_template_var method
     pub fn register_content_template(&mut self, content: &str) -> AppResult<&mut Self> {
         self.base
-            .register_template_string("__base_content", content)?;
+            .register_template("__base_content", self.cache.get("__base_content", content)?);
 
         self.base_content = true;
+        self.source.content = Some(content.to_string());
 
         Ok(self)
     }
 
+    /// Directory where the overrides of the named content templates are looked up,
+    /// see `register_named_content_template`.
+    pub fn set_template_dir(&mut self, dir: &Path) {
+        self.template_dir = Some(dir.to_path_buf());
+    }
+
+    /// Register the content template that can be overridden by a `{name}.hbs` file from
+    /// the template directory, when there is no such file the given default content is used.
+    /// Overrides have access to the same data and get the same layout as the default content.
+    ///
+    /// The override can also be an `.html` file and its name can be spelled with dashes
+    /// instead of underscores, e.g. `test-email.html` for the `test_email` template.
+    ///
+    /// With a locale, the override in its language is preferred, e.g. `{name}.pt-BR.hbs`
+    /// and then `{name}.pt.hbs`, so the locale has to be set before the content is registered.
+    pub fn register_named_content_template(
+        &mut self,
+        name: &str,
+        default_content: &str,
+    ) -> AppResult<&mut Self> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(Error::BadRequest(format!(
+                "invalid_template_name_provided:{name}"
+            )));
+        }
+
+        let mut names = vec![];
+
+        if let Some(locale) = self.source.locale.as_deref() {
+            names.push(format!("{name}.{locale}"));
+
+            if let Some((language, _)) = locale.split_once('-') {
+                names.push(format!("{name}.{language}"));
+            }
+        }
+
+        names.push(name.to_string());
+
+        let files = names
+            .iter()
+            .flat_map(|name| [name.clone(), name.replace('_', "-")])
+            .flat_map(|name| [format!("{name}.hbs"), format!("{name}.html")])
+            .fold(vec![], |mut files, file| {
+                if !files.contains(&file) {
+                    files.push(file);
+                }
+
+                files
+            });
+
+        let content = self
+            .template_dir
+            .iter()
+            .flat_map(|dir| files.iter().map(|file| dir.join(file)))
+            .find_map(|path| match std::fs::read_to_string(&path) {
+                Ok(content) => {
+                    log::debug!("Using email template override: {}", path.display());
+
+                    Some(content)
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => {
+                    log::warn!("Failed reading email template {}: {}", path.display(), e);
+
+                    None
+                }
+            });
+
+        self.register_content_template(content.as_deref().unwrap_or(default_content))
+    }
+
     /// Add a sender to the email
-    pub fn from(mut self, from: &str) -> AppResult<Self> {
+    pub fn from(self, from: &str) -> AppResult<Self> {
         let mailbox = from
             .parse()
             .map_err(|_| Error::BadRequest(format!("invalid_from_address_provided:{from}")))?;
 
-        self.builder = self.builder.from(mailbox);
-
-        self.has_from = true;
-
-        Ok(self)
+        Ok(self.from_mailbox(&mailbox))
     }
 
-    /// Add a sender to the email
+    /// Add a sender to the email, it replaces the sender the email already has
     pub fn from_mailbox(mut self, from: &Mailbox) -> Self {
-        self.builder = self.builder.from(from.clone());
+        self.source.from = Some(from.to_string());
 
         self.has_from = true;
 
         self
     }
 
+    /// Show the email as sent by the given name, e.g. "Alice via Hoodik", the address
+    /// stays the default from address of the sender. This is ignored when the
+    /// template has its own from address.
+    pub fn from_display_name(mut self, name: &str) -> Self {
+        self.source.from_display_name = Some(name.to_string());
+
+        self
+    }
+
+    /// Set the sender's default from when the template has no from address,
+    /// with the display name of the template if it has one.
+    pub fn default_from(self, default_from: &Mailbox) -> Self {
+        if self.has_from {
+            return self;
+        }
+
+        let from = match self.source.from_display_name.as_deref() {
+            Some(name) => Mailbox::new(Some(name.to_string()), default_from.email.clone()),
+            None => default_from.clone(),
+        };
+
+        self.from_mailbox(&from)
+    }
+
     /// Add a reply_to field on the email
-    pub fn reply_to(mut self, reply_to: &str) -> AppResult<Self> {
+    pub fn reply_to(self, reply_to: &str) -> AppResult<Self> {
         let mailbox = reply_to.parse().map_err(|_| {
             Error::BadRequest(format!("invalid_reply_to_address_provided:{reply_to}"))
         })?;
 
-        self.builder = self.builder.reply_to(mailbox);
-
-        self.has_reply_to = true;
-
-        Ok(self)
+        Ok(self.reply_to_mailbox(&mailbox))
     }
 
     /// Add a reply to field on the email
     pub fn reply_to_mailbox(mut self, reply_to: &Mailbox) -> Self {
         self.builder = self.builder.reply_to(reply_to.clone());
+        self.source.reply_to = Some(reply_to.to_string());
 
         self.has_reply_to = true;
 
         self
     }
 
-    /// Add a recipient of the email
+    /// Add a recipient of the email, calling it again adds another recipient
+    /// to the same message. The address is trimmed and parsed right away,
+    /// so a malformed one fails here instead of when the message is built.
     pub fn to(self, to: &str) -> AppResult<Self> {
+        let to = to.trim();
+
         let mailbox = to
-            .parse()
+            .parse::<Mailbox>()
             .map_err(|_| Error::BadRequest(format!("invalid_to_address_provided:{to}")))?;
 
         Ok(self.to_mailbox(&mailbox))
     }
 
+    /// Add multiple recipients to a single message, the recipients can see each other,
+    /// use `announcement` when they shouldn't.
+    pub fn to_many(mut self, to: &[&str]) -> AppResult<Self> {
+        for recipient in to {
+            self = self.to(recipient)?;
+        }
+
+        Ok(self)
+    }
+
     /// Add a recipient to the email
     pub fn to_mailbox(mut self, to: &Mailbox) -> Self {
-        self.builder = self.builder.to(to.clone());
+        self.source.to.push(to.to_string());
 
         if to.email.domain() == "test.com" {
             self.skip_send = true;
@@ -140,112 +557,1581 @@ impl Template {
         self
     }
 
-    /// Generate the final HTML of the email
-    pub fn render(&self) -> AppResult<String> {
-        self.base
-            .render("__base_template", &self.data)
-            .map_err(Error::from)
+    /// Mark the email as no longer relevant after the given time, this emits
+    /// the `Expiry-Date` header (RFC 4021) so clients can reflect the expiry.
+    pub fn expires_at(mut self, expires_at: DateTime<Utc>) -> Self {
+        self.builder = self.builder.raw_header(HeaderValue::new(
+            HeaderName::new_from_ascii_str("Expiry-Date"),
+            expires_at.to_rfc2822(),
+        ));
+        self.source.expires_at = Some(expires_at.timestamp());
+
+        self
     }
 
-    /// Does the current template have a from field defined
-    pub fn has_from(&self) -> bool {
-        self.has_from
+    /// Date of the email instead of the time its message is built, e.g. for a notification
+    /// about an earlier event or a message that has to be the same on every build.
+    /// The header has a precision of seconds.
+    pub fn set_date(&mut self, date: DateTime<Utc>) {
+        self.source.date = Some(date.timestamp());
     }
 
-    /// Is this email marked to not be sent
-    pub fn skip_send(&self) -> bool {
-        self.skip_send
+    /// Let the recipient unsubscribe from notification emails, this emits the
+    /// `List-Unsubscribe` header, along with one-click unsubscribe (RFC 8058)
+    /// for an `https:` URL. The value has to be a `mailto:` or `https:` URL.
+    ///
+    /// Transactional emails (e.g. password reset) should not set this.
+    pub fn set_list_unsubscribe(&mut self, url: &str) -> AppResult<()> {
+        let valid = match url.split_once(':') {
+            Some(("mailto", address)) => address.contains('@'),
+            Some(("https", rest)) => rest.len() > 2 && rest.starts_with("//"),
+            _ => false,
+        };
+
+        if !valid || url.contains(['<', '>', ',', ' ']) {
+            return Err(Error::BadRequest(format!(
+                "invalid_list_unsubscribe_provided:{url}"
+            )));
+        }
+
+        self.source.list_unsubscribe = Some(url.to_string());
+
+        Ok(())
     }
 
-    /// Does the current template have a to field defined
-    pub fn has_to(&self) -> bool {
-        self.has_to
+    /// Remove the `List-Unsubscribe` headers, e.g. when a transactional email
+    /// is created out of a notification template.
+    pub fn clear_list_unsubscribe(&mut self) {
+        self.source.list_unsubscribe = None;
     }
 
-    /// Does the current template have a reply_to field defined
-    pub fn has_reply_to(&self) -> bool {
-        self.has_reply_to
+    /// Flag the priority of the email, without it no priority headers are sent
+    pub fn set_priority(&mut self, priority: EmailPriority) {
+        self.source.priority = Some(priority);
     }
 
-    /// Generate the final email message
-    pub fn message(&self) -> AppResult<Message> {
-        let html = self.render()?;
+    /// Encode the HTML body with the given Content-Transfer-Encoding instead of the one
+    /// picked by its content, the picked one is used when the body can't be encoded with it
+    pub fn set_transfer_encoding(&mut self, encoding: TransferEncoding) {
+        self.source.transfer_encoding = Some(encoding);
+    }
 
-        self.builder
-            .clone()
-            .header(ContentType::TEXT_HTML)
-            .body(html)
-            .map_err(Error::from)
+    /// Language of the email, it picks the overrides of the named content templates,
+    /// see `register_named_content_template`
+    pub fn set_locale(&mut self, locale: &str) -> AppResult<()> {
+        let valid = !locale.is_empty()
+            && locale
+                .split('-')
+                .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric()));
+
+        if !valid {
+            return Err(Error::BadRequest(format!(
+                "invalid_locale_provided:{locale}"
+            )));
+        }
+
+        self.source.locale = Some(locale.to_string());
+
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::Template;
+    /// Language of the email when it was given one
+    pub fn locale(&self) -> Option<&str> {
+        self.source.locale.as_deref()
+    }
 
-    #[test]
-    fn template_can_be_created() {
-        let template = Template::new("subject", "pre_header").unwrap();
+    /// Timezone the dates are formatted in with `format_datetime`, UTC unless told otherwise
+    pub fn set_timezone(&mut self, timezone: FixedOffset) {
+        self.source.timezone = Some(timezone.local_minus_utc());
+    }
 
-        assert!(!template.base_content);
+    /// Format the date for the content of the email in the timezone of the email
+    pub fn format_datetime(&self, at: DateTime<Utc>) -> String {
+        match self.source.timezone.and_then(FixedOffset::east_opt) {
+            Some(timezone) if timezone.local_minus_utc() != 0 => at
+                .with_timezone(&timezone)
+                .format("%Y-%m-%d %H:%M:%S %:z")
+                .to_string(),
+            _ => at.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+        }
     }
 
-    #[test]
-    fn template_can_be_given_base_content() {
-        let mut template = Template::new("subject", "pre_header").unwrap();
+    /// Set the category of the email, emails are transactional unless told otherwise
+    pub fn category(mut self, category: EmailCategory) -> Self {
+        self.source.category = category;
 
-        template
-            .register_content_template("Some Extra Content Template {{ arbitrary_var }}")
-            .unwrap();
+        self
+    }
 
-        assert!(template.base_content);
+    /// Override how the sender retries and times out this email, e.g. a password reset
+    /// can fail fast while a bulk notification can wait longer for a slow relay.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.source.retry_policy = retry_policy;
+
+        self
     }
 
-    #[test]
-    fn template_will_render_and_have_the_extra_content_var() {
-        let mut template = Template::new("subject", "pre_header").unwrap();
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.source.retry_policy
+    }
 
-        template
-            .register_content_template("Some Extra Content Template {{ arbitrary_var }}")
-            .unwrap();
+    /// Ask the receiving servers not to answer the email with vacation and other automatic
+    /// replies, transactional emails ask for it unless told otherwise. Emails that expect
+    /// a reply should turn it off.
+    pub fn suppress_auto_replies(mut self, suppress: bool) -> Self {
+        self.source.suppress_auto_replies = Some(suppress);
 
-        template.add_template_var("arbitrary_var", "---this is the extra content---");
+        self
+    }
 
-        let html = template.render().unwrap();
+    fn suppresses_auto_replies(&self) -> bool {
+        self.source
+            .suppress_auto_replies
+            .unwrap_or(self.source.category == EmailCategory::Transactional)
+    }
 
-        assert!(html.contains("---this is the extra content---"));
+    /// Is this a notification the recipients can opt out of
+    pub fn is_notification(&self) -> bool {
+        self.source.category == EmailCategory::Notification
     }
 
-    #[test]
-    fn template_fails_to_build_message_without_from() {
-        let template = Template::new("subject", "pre_header").unwrap();
+    /// Mark the email to not be sent because the recipients opted out of the
+    /// notifications, transactional emails are always sent so they are left as they are.
+    pub fn opt_out(mut self) -> Self {
+        if self.is_notification() {
+            self.skip_send = true;
+        }
 
-        let message = template.message();
+        self
+    }
 
-        assert_eq!(format!("{:?}", message), "Err(LettreError(MissingFrom))");
+    /// Attach a calendar event (ICS) so the recipients can add it to their calendar,
+    /// it is sent as a `text/calendar; method=REQUEST` alternative of the HTML body.
+    pub fn attach_calendar(&mut self, ics: &str) -> AppResult<()> {
+        let valid = ics
+            .trim_start()
+            .get(..15)
+            .is_some_and(|start| start.eq_ignore_ascii_case("BEGIN:VCALENDAR"));
+
+        if !valid {
+            return Err(Error::BadRequest(
+                "invalid_calendar_provided:must_begin_with_vcalendar".to_string(),
+            ));
+        }
+
+        self.source.calendar = Some(ics.to_string());
+
+        Ok(())
     }
 
-    #[test]
-    fn template_fails_to_build_message_without_to() {
-        let mut template = Template::new("subject", "pre_header").unwrap();
+    /// Generate the Message-ID with the given domain instead of the hostname of the machine,
+    /// this is set by the sender out of its configuration right before sending.
+    pub fn message_id_domain(mut self, domain: &str) -> Self {
+        self.message_id_domain = Some(domain.to_string());
 
-        template = template.from("test@email.com").unwrap();
+        self
+    }
 
-        let message = template.message();
+    /// Add a hidden recipient to the email
+    pub fn bcc(self, bcc: &str) -> AppResult<Self> {
+        let mailbox = bcc
+            .parse()
+            .map_err(|_| Error::BadRequest(format!("invalid_bcc_address_provided:{bcc}")))?;
 
-        assert_eq!(format!("{:?}", message), "Err(LettreError(MissingTo))");
+        Ok(self.bcc_mailbox(&mailbox))
     }
 
-    #[test]
-    fn template_can_have_multiple_recipients() {
-        let mut template = Template::new("subject", "pre_header").unwrap();
+    /// Add a hidden recipient to the email
+    pub fn bcc_mailbox(mut self, bcc: &Mailbox) -> Self {
+        self.builder = self.builder.bcc(bcc.clone());
+        self.source.bcc.push(bcc.to_string());
 
-        template = template.from("from@email.com").unwrap();
-        template = template.to("to1@email.com").unwrap();
-        template = template.to("to2@email.com").unwrap();
+        self
+    }
 
-        let message = template.message();
+    /// Turn the template into an announcement, where all the recipients are put
+    /// on BCC of as few messages as possible, with at most `max_per_message`
+    /// recipients on each message.
+    ///
+    /// Recipients will not see each other, but they all receive the exact same
+    /// content, so never use this for personalized or sensitive emails.
+    pub fn announcement(self, recipients: &[&str], max_per_message: usize) -> AppResult<Vec<Self>> {
+        let mut announcements = vec![];
 
-        assert!(message.is_ok());
+        for chunk in recipients.chunks(max_per_message.max(1)) {
+            let mut announcement = self.clone();
+
+            for recipient in chunk {
+                announcement = announcement.bcc(recipient)?;
+            }
+
+            announcements.push(announcement);
+        }
+
+        Ok(announcements)
+    }
+
+    /// Copy of the email with the given address as its only recipient, the content, variables,
+    /// headers and the from stay as they are, so one template can be mapped over the recipients.
+    /// Hidden recipients are copied too, an email that is skipped stays skipped.
+    pub fn for_recipient(&self, to: &str) -> AppResult<Self> {
+        let mut email = self.clone();
+        email.source.to.clear();
+        email.has_to = false;
+
+        email.to(to)
+    }
+
+    /// Email of a single recipient of a personalized batch, the shared content stays parsed
+    /// once and only the variables of the recipient are added on top of the template ones.
+    /// The recipient replaces the `to` of the template, see [Template::for_recipient].
+    pub fn personalized(&self, recipient: &Personalization) -> AppResult<Self> {
+        let mut email = self.for_recipient(&recipient.to)?;
+
+        for (key, value) in recipient.vars.iter() {
+            email.add_template_var(key, value);
+        }
+
+        Ok(email)
+    }
+
+    /// Emails of every recipient of a personalized batch, in the same order as the recipients
+    pub fn personalize(&self, recipients: &[Personalization]) -> AppResult<Vec<Self>> {
+        recipients
+            .iter()
+            .map(|recipient| self.personalized(recipient))
+            .collect()
+    }
+
+    /// Fail the rendering when a placeholder has no value instead of leaving it empty,
+    /// so a forgotten `add_template_var` is caught before the email is delivered.
+    /// Templates that legitimately show braces should be left lenient.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.source.strict = strict;
+        self.base.set_strict_mode(strict);
+        self
+    }
+
+    /// Render the template into the final HTML, the same as [Template::render_html]
+    pub fn render(&self) -> AppResult<String> {
+        self.render_html()
+    }
+
+    /// Generate the final HTML of the email, the variables substituted into the content
+    /// and the layout, without building the message, e.g. for previews or the logs.
+    pub fn render_html(&self) -> AppResult<String> {
+        if self.source.strict {
+            // Lists all the missing values at once, the renderer only names the first one
+            let unresolved = [&self.source.content, &self.source.extra_head]
+                .into_iter()
+                .flatten()
+                .flat_map(|source| unresolved_placeholders(source, &self.data))
+                .fold(vec![], |mut unresolved, placeholder| {
+                    if !unresolved.contains(&placeholder) {
+                        unresolved.push(placeholder);
+                    }
+                    unresolved
+                });
+
+            if !unresolved.is_empty() {
+                return Err(Error::InternalError(format!(
+                    "unresolved_template_placeholders:{}",
+                    unresolved.join(",")
+                )));
+            }
+        }
+
+        self.base
+            .render("__base_template", &self.data)
+            .map_err(Error::from)
+    }
+
+    /// Render only the content of the email without the layout around it, e.g. to put
+    /// it into a digest, an email without a content template has no content.
+    pub fn render_content(&self) -> AppResult<String> {
+        if !self.base_content {
+            return Ok(String::new());
+        }
+
+        self.base
+            .render("__base_content", &self.data)
+            .map_err(Error::from)
+    }
+
+    /// Body of the HTML part with the configured transfer encoding
+    fn html_body(&self, html: String) -> Body {
+        let encoding = match self.source.transfer_encoding {
+            Some(TransferEncoding::SevenBit) => ContentTransferEncoding::SevenBit,
+            Some(TransferEncoding::QuotedPrintable) => ContentTransferEncoding::QuotedPrintable,
+            Some(TransferEncoding::Base64) => ContentTransferEncoding::Base64,
+            None => return Body::new(html),
+        };
+
+        // 7bit can't carry non-ASCII text or lines over 998 characters
+        Body::new_with_encoding(html.clone(), encoding).unwrap_or_else(|_| {
+            log::warn!(
+                "The email body can't be encoded as MAIL_TRANSFER_ENCODING {:?}, using the default encoding",
+                encoding
+            );
+
+            Body::new(html)
+        })
+    }
+
+    /// Embed an image into the email instead of linking it, clients often block linked images.
+    /// The HTML shows it with `<img src="cid:{cid}">`, embedding the same cid again replaces it.
+    pub fn embed_image(&mut self, cid: &str, content_type: &str, bytes: Vec<u8>) -> AppResult<()> {
+        let valid_cid = !cid.is_empty()
+            && !cid.contains(|c: char| c.is_whitespace() || c == '<' || c == '>' || c == '"');
+
+        if !valid_cid {
+            return Err(Error::BadRequest(
+                "invalid_inline_image_provided:invalid_cid".to_string(),
+            ));
+        }
+
+        let is_image = content_type.to_ascii_lowercase().starts_with("image/")
+            && ContentType::parse(content_type).is_ok();
+
+        if !is_image {
+            return Err(Error::BadRequest(
+                "invalid_inline_image_provided:must_be_an_image".to_string(),
+            ));
+        }
+
+        self.source.inline_images.retain(|image| image.cid != cid);
+        self.source.inline_images.push(InlineImage {
+            cid: cid.to_string(),
+            content_type: content_type.to_string(),
+            content: STANDARD.encode(bytes),
+        });
+
+        Ok(())
+    }
+
+    /// Does the current template have a from field defined
+    pub fn has_from(&self) -> bool {
+        self.has_from
+    }
+
+    /// Is this email marked to not be sent
+    pub fn skip_send(&self) -> bool {
+        self.skip_send
+    }
+
+    /// Email addresses of all the recipients, including the hidden ones
+    pub fn recipients(&self) -> Vec<String> {
+        self.source
+            .to
+            .iter()
+            .chain(self.source.bcc.iter())
+            .filter_map(|mailbox| mailbox.parse::<Mailbox>().ok())
+            .map(|mailbox| mailbox.email.to_string())
+            .collect()
+    }
+
+    /// From address of the email when it has one
+    pub fn from_address(&self) -> Option<Mailbox> {
+        self.source.from.as_deref()?.parse().ok()
+    }
+
+    /// Subject of the email, with the prefix if it was given one
+    pub fn subject(&self) -> &str {
+        &self.source.subject
+    }
+
+    /// Does the current template have a to field defined
+    pub fn has_to(&self) -> bool {
+        self.has_to
+    }
+
+    /// Does the current template have a reply_to field defined
+    pub fn has_reply_to(&self) -> bool {
+        self.has_reply_to
+    }
+
+    /// Generate the final email message
+    pub fn message(&self) -> AppResult<Message> {
+        let html = self.html_body(self.render_html()?);
+        let mut builder = self.builder.clone();
+
+        // Added only now, so that the sender and the recipients can still be replaced
+        if let Some(from) = self.from_address() {
+            builder = builder.from(from);
+        }
+
+        for to in self.source.to.iter() {
+            builder = builder.to(to.parse()?);
+        }
+
+        if let Some(date) = self
+            .source
+            .date
+            .and_then(|date| DateTime::from_timestamp(date, 0))
+        {
+            builder = builder.date(date.into());
+        }
+
+        if let Some(domain) = self.message_id_domain.as_deref() {
+            builder = builder.message_id(Some(format!("<{}@{}>", uuid::Uuid::new_v4(), domain)));
+        }
+
+        if let Some(list_unsubscribe) = self.source.list_unsubscribe.as_deref() {
+            builder = builder.raw_header(HeaderValue::new(
+                HeaderName::new_from_ascii_str("List-Unsubscribe"),
+                format!("<{list_unsubscribe}>"),
+            ));
+
+            // One-click unsubscribe (RFC 8058) is only defined for an HTTPS URL
+            if list_unsubscribe.starts_with("https:") {
+                builder = builder.raw_header(HeaderValue::new(
+                    HeaderName::new_from_ascii_str("List-Unsubscribe-Post"),
+                    "List-Unsubscribe=One-Click".to_string(),
+                ));
+            }
+        }
+
+        if let Some(priority) = self.source.priority {
+            for (name, value) in priority.headers() {
+                builder = builder.raw_header(HeaderValue::new(
+                    HeaderName::new_from_ascii_str(name),
+                    value.to_string(),
+                ));
+            }
+        }
+
+        if self.suppresses_auto_replies() {
+            builder = builder
+                .raw_header(HeaderValue::new(
+                    HeaderName::new_from_ascii_str("Auto-Submitted"),
+                    "auto-generated".to_string(),
+                ))
+                .raw_header(HeaderValue::new(
+                    HeaderName::new_from_ascii_str("X-Auto-Response-Suppress"),
+                    "All".to_string(),
+                ));
+        }
+
+        let images = &self.source.inline_images;
+
+        let calendar = match self.source.calendar.as_deref() {
+            Some(calendar) => calendar,
+            None if images.is_empty() => {
+                return builder
+                    .header(ContentType::TEXT_HTML)
+                    .body(html)
+                    .map_err(Error::from)
+            }
+            None => {
+                return builder
+                    .multipart(related(html, images)?)
+                    .map_err(Error::from)
+            }
+        };
+
+        let calendar_type = ContentType::parse("text/calendar; method=REQUEST; charset=utf-8")
+            .map_err(|e| Error::InternalError(format!("invalid_calendar_content_type:{e}")))?;
+
+        // The images belong to the HTML, so they stay with it as one of the alternatives
+        let alternative = match images.is_empty() {
+            true => MultiPart::alternative().singlepart(SinglePart::html(html)),
+            false => MultiPart::alternative().multipart(related(html, images)?),
+        };
+
+        // Mail clients show the event from the calendar part and fall back to the HTML
+        builder
+            .multipart(
+                alternative.singlepart(
+                    SinglePart::builder()
+                        .header(calendar_type)
+                        .body(calendar.to_string()),
+                ),
+            )
+            .map_err(Error::from)
+    }
+}
+
+/// Subject with the prefix in front of it, the subjects that already start with it are
+/// left as they are, so prefixing a subject again doesn't repeat the prefix.
+pub fn prefix_subject(prefix: Option<&str>, subject: &str) -> String {
+    match prefix.map(str::trim).filter(|prefix| !prefix.is_empty()) {
+        Some(prefix) if !subject.starts_with(prefix) => format!("{prefix} {subject}"),
+        _ => subject.to_string(),
+    }
+}
+
+/// HTML together with the images it references
+fn related(html: Body, images: &[InlineImage]) -> AppResult<MultiPart> {
+    images.iter().try_fold(
+        MultiPart::related().singlepart(SinglePart::html(html)),
+        |related, image| Ok(related.singlepart(image.part()?)),
+    )
+}
+
+/// Placeholders of the template source without a value in the data, e.g. `{{missing}}`
+/// gives `missing`. Only the plain placeholders outside of the blocks are checked, the ones
+/// in the blocks and the helpers are left to the strict mode of the renderer.
+fn unresolved_placeholders(source: &str, data: &BTreeMap<String, Value>) -> Vec<String> {
+    let mut unresolved = vec![];
+    let mut depth = 0usize;
+    let mut rest = source;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+
+        let expression = rest[start + 2..start + end]
+            .trim_matches(|c: char| c == '{' || c == '~' || c.is_whitespace());
+
+        rest = &rest[start + end + 2..];
+
+        if expression.starts_with('#') {
+            depth += 1;
+            continue;
+        }
+
+        if expression.starts_with('/') {
+            depth = depth.saturating_sub(1);
+            continue;
+        }
+
+        if depth > 0
+            || expression.is_empty()
+            || expression == "else"
+            || expression.starts_with(['!', '>', '^', '@', '.'])
+            || expression.contains(char::is_whitespace)
+            || expression == "this"
+            || expression.starts_with("this.")
+        {
+            continue;
+        }
+
+        let name = expression
+            .split(['.', '/', '['])
+            .next()
+            .unwrap_or(expression);
+
+        if !data.contains_key(name) && !unresolved.iter().any(|known| known == name) {
+            unresolved.push(name.to_string());
+        }
+    }
+
+    unresolved
+}
+
+/// HTML-escape all the strings in the value, the keys of the objects are left as they are
+fn escape_strings(value: &mut Value) {
+    match value {
+        Value::String(string) => *string = html_escape(string),
+        Value::Array(values) => values.iter_mut().for_each(escape_strings),
+        Value::Object(values) => values.values_mut().for_each(escape_strings),
+        Value::Null | Value::Bool(_) | Value::Number(_) => (),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        prefix_subject, EmailCategory, EmailPriority, Personalization, RetryPolicy, Template,
+    };
+    use error::{AppResult, Error};
+
+    #[test]
+    fn template_can_be_created() {
+        let template = Template::new("subject", "pre_header").unwrap();
+
+        assert!(!template.base_content);
+    }
+
+    #[test]
+    fn template_can_be_given_base_content() {
+        let mut template = Template::new("subject", "pre_header").unwrap();
+
+        template
+            .register_content_template("Some Extra Content Template {{ arbitrary_var }}")
+            .unwrap();
+
+        assert!(template.base_content);
+    }
+
+    #[test]
+    fn template_will_render_and_have_the_extra_content_var() {
+        let mut template = Template::new("subject", "pre_header").unwrap();
+
+        template
+            .register_content_template("Some Extra Content Template {{ arbitrary_var }}")
+            .unwrap();
+
+        template.add_template_var("arbitrary_var", "---this is the extra content---");
+
+        let html = template.render().unwrap();
+
+        assert!(html.contains("---this is the extra content---"));
+    }
+
+    #[test]
+    fn template_renders_without_building_the_message() {
+        let mut template = Template::new("Preview subject", "pre_header").unwrap();
+
+        template
+            .register_content_template("<p>Hello {{ name }}</p>")
+            .unwrap();
+        template.add_template_var("name", "John");
+
+        // Nobody to send it to, the HTML can still be previewed
+        assert!(template.message().is_err());
+
+        let html = template.render_html().unwrap();
+
+        assert!(html.contains("<p>Hello John</p>"));
+        assert!(html.contains("Preview subject"));
+    }
+
+    #[test]
+    fn template_escapes_template_vars() {
+        let mut template = Template::new("subject", "pre_header").unwrap();
+
+        template
+            .register_content_template("<p>{{ name }}</p>")
+            .unwrap();
+
+        template.add_template_var("name", "<script>alert('x')</script>");
+
+        let html = template.render_html().unwrap();
+
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("<p>&lt;script&gt;alert(&#x27;x&#x27;)&lt;/script&gt;</p>"));
+    }
+
+    #[test]
+    fn template_keeps_raw_template_vars() {
+        let mut template = Template::new("subject", "pre_header").unwrap();
+
+        template
+            .register_content_template("<div>{{ markup }}</div>")
+            .unwrap();
+
+        template.add_raw_template_var("markup", "<strong>bold</strong>");
+
+        let html = template.render_html().unwrap();
+
+        assert!(html.contains("<div><strong>bold</strong></div>"));
+    }
+
+    #[test]
+    fn template_fails_to_build_message_without_from() {
+        let template = Template::new("subject", "pre_header").unwrap();
+
+        let message = template.message();
+
+        assert_eq!(format!("{:?}", message), "Err(LettreError(MissingFrom))");
+    }
+
+    #[test]
+    fn template_fails_to_build_message_without_to() {
+        let mut template = Template::new("subject", "pre_header").unwrap();
+
+        template = template.from("test@email.com").unwrap();
+
+        let message = template.message();
+
+        assert_eq!(format!("{:?}", message), "Err(LettreError(MissingTo))");
+    }
+
+    #[test]
+    fn template_emits_expiry_date_when_set() {
+        let expires_at = chrono::DateTime::parse_from_rfc3339("2030-01-02T03:04:05Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+
+        let template = Template::new("subject", "pre_header")
+            .unwrap()
+            .from("from@email.com")
+            .unwrap()
+            .to("to@email.com")
+            .unwrap()
+            .expires_at(expires_at);
+
+        let formatted = String::from_utf8(template.message().unwrap().formatted()).unwrap();
+
+        assert!(formatted.contains("Expiry-Date: Wed, 2 Jan 2030 03:04:05 +0000\r\n"));
+    }
+
+    #[test]
+    fn template_uses_the_date_it_was_given() {
+        let date = chrono::DateTime::parse_from_rfc3339("2030-01-02T03:04:05Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+
+        let mut template = Template::new("subject", "pre_header")
+            .unwrap()
+            .from("from@email.com")
+            .unwrap()
+            .to("to@email.com")
+            .unwrap();
+
+        let formatted = String::from_utf8(template.message().unwrap().formatted()).unwrap();
+
+        // The time the message is built
+        assert!(formatted.contains("Date: "));
+        assert!(!formatted.contains("Date: Wed, 02 Jan 2030 03:04:05 +0000\r\n"));
+
+        template.set_date(date);
+
+        let formatted = String::from_utf8(template.message().unwrap().formatted()).unwrap();
+
+        assert!(formatted.contains("Date: Wed, 02 Jan 2030 03:04:05 +0000\r\n"));
+
+        let rebuilt = Template::from_payload(template.payload()).unwrap();
+        let formatted = String::from_utf8(rebuilt.message().unwrap().formatted()).unwrap();
+
+        assert!(formatted.contains("Date: Wed, 02 Jan 2030 03:04:05 +0000\r\n"));
+    }
+
+    #[test]
+    fn template_omits_expiry_date_when_unset() {
+        let template = Template::new("subject", "pre_header")
+            .unwrap()
+            .from("from@email.com")
+            .unwrap()
+            .to("to@email.com")
+            .unwrap();
+
+        let formatted = String::from_utf8(template.message().unwrap().formatted()).unwrap();
+
+        assert!(!formatted.contains("Expiry-Date"));
+    }
+
+    #[test]
+    fn template_announcement_puts_recipients_on_bcc() {
+        let recipients = [
+            "1@email.com",
+            "2@email.com",
+            "3@email.com",
+            "4@email.com",
+            "5@email.com",
+        ];
+
+        let template = Template::new("subject", "pre_header")
+            .unwrap()
+            .from("from@email.com")
+            .unwrap();
+
+        let announcements = template
+            .announcement(&recipients, super::ANNOUNCEMENT_MAX_RECIPIENTS)
+            .unwrap();
+
+        assert_eq!(announcements.len(), 1);
+
+        let message = announcements[0].message().unwrap();
+        let formatted = String::from_utf8(message.formatted()).unwrap();
+
+        assert_eq!(message.envelope().to().len(), 5);
+        assert!(!formatted.contains("Bcc"));
+        assert!(!formatted.contains("1@email.com"));
+    }
+
+    #[test]
+    fn template_announcement_respects_recipient_cap() {
+        let recipients = [
+            "1@email.com",
+            "2@email.com",
+            "3@email.com",
+            "4@email.com",
+            "5@email.com",
+        ];
+
+        let template = Template::new("subject", "pre_header")
+            .unwrap()
+            .from("from@email.com")
+            .unwrap();
+
+        let announcements = template.announcement(&recipients, 2).unwrap();
+
+        let sizes = announcements
+            .iter()
+            .map(|a| a.message().unwrap().envelope().to().len())
+            .collect::<Vec<_>>();
+
+        assert_eq!(sizes, vec![2, 2, 1]);
+    }
+
+    #[test]
+    fn template_emits_list_unsubscribe_headers() {
+        let mut template = Template::new("subject", "pre_header")
+            .unwrap()
+            .from("from@email.com")
+            .unwrap()
+            .to("to@email.com")
+            .unwrap();
+
+        template
+            .set_list_unsubscribe("https://example.com/unsubscribe?id=1")
+            .unwrap();
+
+        let formatted = String::from_utf8(template.message().unwrap().formatted()).unwrap();
+
+        assert!(formatted.contains("List-Unsubscribe: <https://example.com/unsubscribe?id=1>\r\n"));
+        assert!(formatted.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click\r\n"));
+
+        // One-click unsubscribe doesn't apply to a mailto
+        template
+            .set_list_unsubscribe("mailto:unsubscribe@example.com")
+            .unwrap();
+
+        let formatted = String::from_utf8(template.message().unwrap().formatted()).unwrap();
+
+        assert!(formatted.contains("List-Unsubscribe: <mailto:unsubscribe@example.com>\r\n"));
+        assert!(!formatted.contains("List-Unsubscribe-Post"));
+
+        template.clear_list_unsubscribe();
+
+        let formatted = String::from_utf8(template.message().unwrap().formatted()).unwrap();
+
+        assert!(!formatted.contains("List-Unsubscribe"));
+    }
+
+    #[test]
+    fn template_validates_list_unsubscribe() {
+        let mut template = Template::new("subject", "pre_header").unwrap();
+
+        assert!(template
+            .set_list_unsubscribe("mailto:unsubscribe@example.com")
+            .is_ok());
+        assert!(template
+            .set_list_unsubscribe("http://example.com/unsubscribe")
+            .is_err());
+        assert!(template.set_list_unsubscribe("example.com").is_err());
+        assert!(template.set_list_unsubscribe("mailto:nobody").is_err());
+    }
+
+    #[test]
+    fn template_uses_configured_message_id_domain() {
+        let template = Template::new("subject", "pre_header")
+            .unwrap()
+            .from("from@email.com")
+            .unwrap()
+            .to("to@email.com")
+            .unwrap()
+            .message_id_domain("mail.example.com");
+
+        let message = template.message().unwrap();
+        let message_id = message.headers().get_raw("Message-ID").unwrap();
+
+        assert!(message_id.starts_with('<'));
+        assert!(message_id.ends_with("@mail.example.com>"));
+    }
+
+    fn template_dir(name: &str) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("hoodik-templates-{name}-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    #[test]
+    fn template_uses_override_from_template_dir() {
+        let dir = template_dir("override");
+        std::fs::write(dir.join("invitation.hbs"), "<p>Custom {{ name }}</p>").unwrap();
+
+        let mut template = Template::new("subject", "pre_header").unwrap();
+        template.set_template_dir(&dir);
+        template
+            .register_named_content_template("invitation", "<p>Default {{ name }}</p>")
+            .unwrap();
+        template.add_template_var("name", "John");
+
+        let html = template.render_html().unwrap();
+
+        assert!(html.contains("<p>Custom John</p>"));
+        assert!(html.contains("pre_header"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn template_uses_html_override_with_dashed_name() {
+        let dir = template_dir("html");
+        std::fs::write(dir.join("test-email.html"), "<p>Branded {{ app_name }}</p>").unwrap();
+
+        let mut template = Template::new("subject", "pre_header").unwrap();
+        template.set_template_dir(&dir);
+        template.add_template_var("app_name", "Hoodik");
+        template
+            .register_named_content_template("test_email", "<p>Default {{ app_name }}</p>")
+            .unwrap();
+
+        assert!(template
+            .render_html()
+            .unwrap()
+            .contains("<p>Branded Hoodik</p>"));
+
+        // The handlebars file with the name of the template still comes first
+        std::fs::write(dir.join("test_email.hbs"), "<p>Custom {{ app_name }}</p>").unwrap();
+
+        let mut template = Template::new("subject", "pre_header").unwrap();
+        template.set_template_dir(&dir);
+        template.add_template_var("app_name", "Hoodik");
+        template
+            .register_named_content_template("test_email", "<p>Default {{ app_name }}</p>")
+            .unwrap();
+
+        assert!(template
+            .render_html()
+            .unwrap()
+            .contains("<p>Custom Hoodik</p>"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn template_prefers_override_in_its_locale() {
+        let dir = template_dir("locale");
+        std::fs::write(dir.join("invitation.hbs"), "<p>Custom {{ name }}</p>").unwrap();
+        std::fs::write(dir.join("invitation.de.hbs"), "<p>Hallo {{ name }}</p>").unwrap();
+
+        let render = |locale: Option<&str>| {
+            let mut template = Template::new("subject", "pre_header").unwrap();
+            template.set_template_dir(&dir);
+
+            if let Some(locale) = locale {
+                template.set_locale(locale).unwrap();
+            }
+
+            template
+                .register_named_content_template("invitation", "<p>Default {{ name }}</p>")
+                .unwrap();
+            template.add_template_var("name", "John");
+            template.render_html().unwrap()
+        };
+
+        assert!(render(Some("de-AT")).contains("<p>Hallo John</p>"));
+        assert!(render(Some("de")).contains("<p>Hallo John</p>"));
+        assert!(render(Some("fr")).contains("<p>Custom John</p>"));
+        assert!(render(None).contains("<p>Custom John</p>"));
+
+        let mut template = Template::new("subject", "pre_header").unwrap();
+        assert!(template.set_locale("../de").is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn dates_are_formatted_in_the_timezone_of_the_template() {
+        let at = chrono::DateTime::parse_from_rfc3339("2030-01-02T03:04:05Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+
+        let mut template = Template::new("subject", "pre_header").unwrap();
+
+        assert_eq!(template.format_datetime(at), "2030-01-02 03:04:05 UTC");
+
+        template.set_timezone(chrono::FixedOffset::east_opt(2 * 3600).unwrap());
+
+        assert_eq!(template.format_datetime(at), "2030-01-02 05:04:05 +02:00");
+
+        template.set_locale("de").unwrap();
+
+        let rebuilt = Template::from_payload(template.payload()).unwrap();
+
+        assert_eq!(rebuilt.format_datetime(at), "2030-01-02 05:04:05 +02:00");
+        assert_eq!(rebuilt.locale(), Some("de"));
+    }
+
+    #[test]
+    fn template_falls_back_to_default_without_override() {
+        let dir = template_dir("fallback");
+
+        let mut template = Template::new("subject", "pre_header").unwrap();
+        template.set_template_dir(&dir);
+        template
+            .register_named_content_template("invitation", "<p>Default {{ name }}</p>")
+            .unwrap();
+        template.add_template_var("name", "John");
+
+        assert!(template
+            .render_html()
+            .unwrap()
+            .contains("<p>Default John</p>"));
+        assert!(template
+            .register_named_content_template("../invitation", "")
+            .is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn template_content_is_parsed_once_for_many_emails() {
+        let cache: &'static crate::cache::TemplateCache = Box::leak(Box::default());
+
+        for i in 0..1000 {
+            let mut template = Template::new("subject", "pre_header").unwrap();
+            template.cache = cache;
+
+            template
+                .register_content_template("<p>Hello {{ name }}</p>")
+                .unwrap();
+            template.add_template_var("name", i);
+
+            assert!(template
+                .render_html()
+                .unwrap()
+                .contains(&format!("<p>Hello {i}</p>")));
+        }
+
+        assert_eq!(cache.compiled(), 1);
+    }
+
+    #[test]
+    fn template_is_personalized_for_every_recipient() {
+        let mut template = Template::new("subject", "pre_header").unwrap();
+        template
+            .register_content_template("<p>Hello {{ name }} from {{ team }}</p>")
+            .unwrap();
+        template.add_template_var("team", "Hoodik");
+        template.add_template_var("name", "everyone");
+
+        let emails = template
+            .personalize(&[
+                Personalization::new("first@doe.com").var("name", "<First>"),
+                Personalization::new("second@doe.com"),
+            ])
+            .unwrap();
+
+        assert_eq!(emails[0].recipients(), vec!["first@doe.com".to_string()]);
+        assert!(emails[0]
+            .render_html()
+            .unwrap()
+            .contains("<p>Hello &lt;First&gt; from Hoodik</p>"));
+        assert_eq!(emails[1].recipients(), vec!["second@doe.com".to_string()]);
+        assert!(emails[1]
+            .render_html()
+            .unwrap()
+            .contains("<p>Hello everyone from Hoodik</p>"));
+
+        // The shared template gets no recipient
+        assert!(template.recipients().is_empty());
+
+        // A recipient the shared template already had doesn't get every email
+        let template = template.to("shared@doe.com").unwrap();
+        let emails = template
+            .personalize(&[Personalization::new("first@doe.com")])
+            .unwrap();
+
+        assert_eq!(emails[0].recipients(), vec!["first@doe.com".to_string()]);
+    }
+
+    #[test]
+    fn template_is_copied_for_another_recipient() {
+        let mut template = Template::new("subject", "pre_header").unwrap();
+        template
+            .register_content_template("<p>Hello {{ name }}</p>")
+            .unwrap();
+        template.add_template_var("name", "John");
+
+        let template = template
+            .from("sender@doe.com")
+            .unwrap()
+            .to("first@doe.com")
+            .unwrap()
+            .bcc("archive@doe.com")
+            .unwrap();
+
+        let emails = ["second@doe.com", "third@doe.com"]
+            .iter()
+            .map(|to| template.for_recipient(to))
+            .collect::<AppResult<Vec<Template>>>()
+            .unwrap();
+
+        assert_eq!(
+            emails[0].recipients(),
+            vec!["second@doe.com".to_string(), "archive@doe.com".to_string()]
+        );
+        assert_eq!(emails[1].payload().to, vec!["third@doe.com".to_string()]);
+
+        let message = String::from_utf8(emails[0].message().unwrap().formatted()).unwrap();
+
+        assert!(message.contains("To: second@doe.com"));
+        assert!(!message.contains("first@doe.com"));
+        assert!(message.contains("From: sender@doe.com"));
+        assert!(emails[0]
+            .render_html()
+            .unwrap()
+            .contains("<p>Hello John</p>"));
+
+        // The original keeps its recipient, and a test address is still skipped
+        assert_eq!(template.payload().to, vec!["first@doe.com".to_string()]);
+        assert!(template.for_recipient("john@test.com").unwrap().skip_send());
+        assert!(template.for_recipient("not an address").is_err());
+    }
+
+    #[test]
+    fn template_cache_tells_apart_different_content() {
+        let cache: &'static crate::cache::TemplateCache = Box::leak(Box::default());
+
+        let mut first = Template::new("subject", "pre_header").unwrap();
+        first.cache = cache;
+        first.register_content_template("first").unwrap();
+
+        let mut second = Template::new("subject", "pre_header").unwrap();
+        second.cache = cache;
+        second.register_content_template("second").unwrap();
+
+        assert!(first.render_html().unwrap().contains("first"));
+        assert!(second.render_html().unwrap().contains("second"));
+        assert_eq!(cache.compiled(), 2);
+    }
+
+    #[test]
+    fn template_can_be_rebuilt_from_payload() {
+        let expires_at = chrono::DateTime::from_timestamp(1_893_553_445, 0).unwrap();
+
+        let mut template = Template::new("subject", "pre_header")
+            .unwrap()
+            .from("From <from@email.com>")
+            .unwrap()
+            .from_display_name("Alice via Hoodik")
+            .reply_to("reply@email.com")
+            .unwrap()
+            .to("to@email.com")
+            .unwrap()
+            .bcc("bcc@email.com")
+            .unwrap()
+            .expires_at(expires_at)
+            .category(EmailCategory::Notification)
+            .with_retry_policy(RetryPolicy::new(1, std::time::Duration::from_secs(5)));
+
+        template
+            .register_content_template("<p>{{ name }}</p>")
+            .unwrap();
+        template.add_template_var("name", "<b>John</b>");
+        template
+            .set_list_unsubscribe("mailto:unsubscribe@email.com")
+            .unwrap();
+
+        let payload = serde_json::to_string(&template.payload()).unwrap();
+        let rebuilt = Template::from_payload(serde_json::from_str(&payload).unwrap()).unwrap();
+
+        assert_eq!(rebuilt.payload(), template.payload());
+        assert!(rebuilt.is_notification());
+        assert_eq!(rebuilt.retry_policy().retries, Some(1));
+        assert_eq!(
+            rebuilt.render_html().unwrap(),
+            template.render_html().unwrap()
+        );
+
+        let message = rebuilt.message().unwrap();
+
+        assert_eq!(message.envelope().to().len(), 2);
+        let original = template.message().unwrap();
+
+        for header in [
+            "From",
+            "Reply-To",
+            "To",
+            "Subject",
+            "Expiry-Date",
+            "List-Unsubscribe",
+        ] {
+            assert_eq!(
+                message.headers().get_raw(header),
+                original.headers().get_raw(header)
+            );
+        }
+    }
+
+    #[test]
+    fn only_notifications_are_skipped_when_opted_out() {
+        let notification = Template::new("subject", "pre_header")
+            .unwrap()
+            .to("to@email.com")
+            .unwrap()
+            .category(EmailCategory::Notification)
+            .opt_out();
+
+        assert!(notification.skip_send());
+
+        let transactional = Template::new("subject", "pre_header")
+            .unwrap()
+            .to("to@email.com")
+            .unwrap()
+            .opt_out();
+
+        assert!(!transactional.is_notification());
+        assert!(!transactional.skip_send());
+
+        // Queued before the categories existed
+        let payload = r#"{"subject":"s","pre_header":"p","data":{},"content":null,"extra_head":null,"from":null,"reply_to":null,"to":[],"bcc":[],"expires_at":null,"list_unsubscribe":null}"#;
+        let payload: super::TemplatePayload = serde_json::from_str(payload).unwrap();
+
+        assert_eq!(payload.category, EmailCategory::Transactional);
+    }
+
+    #[test]
+    fn template_renders_sections_and_lists() {
+        #[derive(serde::Serialize)]
+        struct Item {
+            name: &'static str,
+            size: u64,
+        }
+
+        let mut template = Template::new("subject", "pre_header").unwrap();
+
+        template
+            .register_content_template(
+                "{{#if shared}}<h2>Shared with {{owner}}</h2>{{/if}}\
+                {{#if missing}}<p>never</p>{{else}}<p>fallback</p>{{/if}}\
+                <ul>{{#each items}}<li>{{@index}}:{{name}} ({{size}})</li>{{/each}}</ul>",
+            )
+            .unwrap();
+
+        template.add_template_var("shared", true);
+        template.add_template_var("owner", "<John>");
+        template
+            .add_template_data(
+                "items",
+                &[
+                    Item {
+                        name: "a.txt",
+                        size: 1,
+                    },
+                    Item {
+                        name: "<b>.txt",
+                        size: 2,
+                    },
+                ],
+            )
+            .unwrap();
+
+        let html = template.render_html().unwrap();
+
+        assert!(html.contains("<h2>Shared with &lt;John&gt;</h2>"));
+        assert!(html.contains("<p>fallback</p>"));
+        assert!(!html.contains("never"));
+        assert!(html.contains("<ul><li>0:a.txt (1)</li><li>1:&lt;b&gt;.txt (2)</li></ul>"));
+
+        // Structured data survives being queued
+        let payload = serde_json::to_string(&template.payload()).unwrap();
+        let rebuilt = Template::from_payload(serde_json::from_str(&payload).unwrap()).unwrap();
+
+        assert_eq!(rebuilt.render_html().unwrap(), html);
+    }
+
+    #[test]
+    fn calendar_is_sent_alongside_the_html() {
+        let mut template = Template::new("subject", "pre_header")
+            .unwrap()
+            .from("from@email.com")
+            .unwrap()
+            .to("to@email.com")
+            .unwrap();
+
+        template.register_content_template("<p>Invite</p>").unwrap();
+
+        assert!(template.attach_calendar("SUMMARY:Meeting").is_err());
+        assert!(template.attach_calendar("").is_err());
+
+        let ics = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nMETHOD:REQUEST\r\nEND:VCALENDAR\r\n";
+
+        template.attach_calendar(ics).unwrap();
+
+        let payload = serde_json::to_string(&template.payload()).unwrap();
+        let rebuilt = Template::from_payload(serde_json::from_str(&payload).unwrap()).unwrap();
+
+        assert_eq!(rebuilt.payload(), template.payload());
+
+        let formatted = String::from_utf8(rebuilt.message().unwrap().formatted()).unwrap();
+
+        assert!(formatted.contains("Content-Type: multipart/alternative"));
+        assert!(formatted.contains("Content-Type: text/html; charset=utf-8"));
+        assert!(formatted.contains("Content-Type: text/calendar; method=REQUEST; charset=utf-8"));
+        assert!(formatted.contains("BEGIN:VCALENDAR"));
+        assert!(formatted.contains("<p>Invite</p>"));
+    }
+
+    #[test]
+    fn inline_images_are_related_to_the_html() {
+        let mut template = Template::new("subject", "pre_header")
+            .unwrap()
+            .from("from@email.com")
+            .unwrap()
+            .to("to@email.com")
+            .unwrap();
+
+        template
+            .register_content_template("<img src=\"cid:logo\">")
+            .unwrap();
+
+        assert!(template.embed_image("", "image/png", vec![1]).is_err());
+        assert!(template
+            .embed_image("<logo>", "image/png", vec![1])
+            .is_err());
+        assert!(template.embed_image("logo", "text/html", vec![1]).is_err());
+
+        template
+            .embed_image("logo", "image/png", vec![0, 1, 2])
+            .unwrap();
+        // Embedding the same cid again replaces the image
+        template
+            .embed_image("logo", "image/png", vec![137, 80, 78, 71])
+            .unwrap();
+
+        let payload = serde_json::to_string(&template.payload()).unwrap();
+        let rebuilt = Template::from_payload(serde_json::from_str(&payload).unwrap()).unwrap();
+
+        assert_eq!(rebuilt.payload(), template.payload());
+        assert_eq!(rebuilt.payload().inline_images.len(), 1);
+
+        let formatted = String::from_utf8(rebuilt.message().unwrap().formatted()).unwrap();
+
+        assert!(formatted.contains("Content-Type: multipart/related"));
+        assert!(!formatted.contains("multipart/alternative"));
+        assert!(formatted.contains("Content-ID: <logo>"));
+        assert!(formatted.contains("Content-Disposition: inline"));
+        assert!(formatted.contains("Content-Type: image/png"));
+        assert!(formatted.contains("iVBORw=="));
+
+        // With a calendar the related HTML is one of the alternatives
+        let ics = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nEND:VCALENDAR\r\n";
+        template.attach_calendar(ics).unwrap();
+
+        let formatted = String::from_utf8(template.message().unwrap().formatted()).unwrap();
+
+        let alternative = formatted.find("multipart/alternative").unwrap();
+        let related = formatted.find("multipart/related").unwrap();
+        let calendar = formatted.find("text/calendar").unwrap();
+
+        assert!(alternative < related && related < calendar);
+    }
+
+    #[test]
+    fn to_address_is_trimmed_and_validated() {
+        let template = Template::new("subject", "pre_header").unwrap();
+
+        let padded = template.clone().to("  John <john@doe.com>\t").unwrap();
+
+        assert_eq!(padded.payload().to, vec!["John <john@doe.com>"]);
+        assert_eq!(padded.recipients(), vec!["john@doe.com"]);
+
+        let valid = template.clone().to("john@doe.com").unwrap();
+
+        assert_eq!(valid.payload().to, vec!["john@doe.com"]);
+
+        match template.clone().to(" john.doe.com ") {
+            Err(Error::BadRequest(message)) => {
+                assert_eq!(message, "invalid_to_address_provided:john.doe.com")
+            }
+            _ => panic!("address without @ must be rejected"),
+        }
+
+        assert!(template.to("   ").is_err());
+    }
+
+    #[test]
+    fn transactional_emails_suppress_auto_replies() {
+        let template = Template::new("subject", "pre_header")
+            .unwrap()
+            .from("from@email.com")
+            .unwrap()
+            .to("to@email.com")
+            .unwrap();
+
+        let formatted = |template: &Template| {
+            String::from_utf8(template.message().unwrap().formatted()).unwrap()
+        };
+
+        let transactional = formatted(&template);
+
+        assert!(transactional.contains("Auto-Submitted: auto-generated\r\n"));
+        assert!(transactional.contains("X-Auto-Response-Suppress: All\r\n"));
+
+        let notification = formatted(&template.clone().category(EmailCategory::Notification));
+
+        assert!(!notification.contains("Auto-Submitted"));
+
+        let expects_reply = template.suppress_auto_replies(false);
+        let rebuilt = Template::from_payload(expects_reply.payload()).unwrap();
+
+        assert!(!formatted(&rebuilt).contains("Auto-Submitted"));
+        assert!(!formatted(&rebuilt).contains("X-Auto-Response-Suppress"));
+    }
+
+    #[test]
+    fn subject_is_prefixed_once() {
+        assert_eq!(
+            prefix_subject(Some("[Hoodik]"), "Reset password"),
+            "[Hoodik] Reset password"
+        );
+        assert_eq!(
+            prefix_subject(Some("[Hoodik]"), "[Hoodik] Reset password"),
+            "[Hoodik] Reset password"
+        );
+        assert_eq!(
+            prefix_subject(Some(" "), "Reset password"),
+            "Reset password"
+        );
+        assert_eq!(prefix_subject(None, "Reset password"), "Reset password");
+    }
+
+    #[test]
+    fn high_priority_sets_the_priority_headers() {
+        let template = Template::new("subject", "pre_header")
+            .unwrap()
+            .from("from@email.com")
+            .unwrap()
+            .to("to@email.com")
+            .unwrap();
+
+        let formatted = String::from_utf8(template.message().unwrap().formatted()).unwrap();
+
+        assert!(!formatted.contains("X-Priority"));
+        assert!(!formatted.contains("Importance"));
+
+        let mut template = template;
+        template.set_priority(EmailPriority::High);
+
+        let rebuilt = Template::from_payload(template.payload()).unwrap();
+        let formatted = String::from_utf8(rebuilt.message().unwrap().formatted()).unwrap();
+
+        assert!(formatted.contains("X-Priority: 1 (Highest)\r\n"));
+        assert!(formatted.contains("Importance: high\r\n"));
+        assert!(formatted.contains("Priority: urgent\r\n"));
+    }
+
+    #[test]
+    fn configured_transfer_encoding_is_used_for_the_body() {
+        use config::email::TransferEncoding;
+
+        let mut template = Template::new("subject", "pre_header")
+            .unwrap()
+            .from("from@email.com")
+            .unwrap()
+            .to("to@email.com")
+            .unwrap();
+
+        template
+            .register_content_template("<p>Plain text</p>")
+            .unwrap();
+
+        let formatted = String::from_utf8(template.message().unwrap().formatted()).unwrap();
+
+        assert!(!formatted.contains("Content-Transfer-Encoding: base64"));
+
+        template.set_transfer_encoding(TransferEncoding::Base64);
+
+        let rebuilt = Template::from_payload(template.payload()).unwrap();
+        let formatted = String::from_utf8(rebuilt.message().unwrap().formatted()).unwrap();
+
+        assert!(formatted.contains("Content-Transfer-Encoding: base64\r\n"));
+
+        // Text that 7bit can't carry falls back to the encoding picked by the content
+        let mut template = Template::new("subject", "pre_header")
+            .unwrap()
+            .from("from@email.com")
+            .unwrap()
+            .to("to@email.com")
+            .unwrap();
+
+        template.register_content_template("<p>Grüße</p>").unwrap();
+        template.set_transfer_encoding(TransferEncoding::SevenBit);
+
+        let formatted = String::from_utf8(template.message().unwrap().formatted()).unwrap();
+
+        assert!(!formatted.contains("Content-Transfer-Encoding: 7bit"));
+    }
+
+    #[test]
+    fn template_puts_many_recipients_on_one_message() {
+        let template = Template::new("subject", "pre_header")
+            .unwrap()
+            .from("from@email.com")
+            .unwrap()
+            .to_many(&["to1@email.com", "to2@email.com"])
+            .unwrap();
+
+        let message = template.message().unwrap();
+        let formatted = String::from_utf8(message.formatted()).unwrap();
+
+        assert_eq!(message.envelope().to().len(), 2);
+        assert!(formatted.contains("To: to1@email.com, to2@email.com\r\n"));
+    }
+
+    #[test]
+    fn template_can_have_multiple_recipients() {
+        let mut template = Template::new("subject", "pre_header").unwrap();
+
+        template = template.from("from@email.com").unwrap();
+        template = template.to("to1@email.com").unwrap();
+        template = template.to("to2@email.com").unwrap();
+
+        let message = template.message();
+
+        assert!(message.is_ok());
+    }
+
+    #[test]
+    fn strict_template_fails_on_missing_values() {
+        let mut template = Template::new("subject", "pre_header").unwrap().strict(true);
+        template
+            .register_content_template("<p>Hello {{name}}, {{missing}}</p>")
+            .unwrap();
+        template.add_template_var("name", "John");
+
+        assert!(template.render_html().is_err());
+
+        template.add_template_var("missing", "found");
+
+        assert!(template
+            .render_html()
+            .unwrap()
+            .contains("<p>Hello John, found</p>"));
+
+        // Lenient templates still render the missing value as empty
+        let mut template = Template::new("subject", "pre_header").unwrap();
+        template
+            .register_content_template("<p>Hello {{missing}}</p>")
+            .unwrap();
+
+        assert!(template.render_html().unwrap().contains("<p>Hello </p>"));
+    }
+
+    #[test]
+    fn strict_template_lists_unresolved_placeholders() {
+        let mut template = Template::new("subject", "pre_header").unwrap().strict(true);
+        template
+            .register_content_template("<p>{{first}} and {{ second }} and {{first}}</p>")
+            .unwrap();
+
+        let error = template.render_html().unwrap_err();
+
+        assert!(error
+            .to_string()
+            .contains("unresolved_template_placeholders:first,second"));
+
+        // Strict mode survives queueing the email
+        let rebuilt = Template::from_payload(template.payload()).unwrap();
+
+        assert!(rebuilt.render_html().is_err());
+    }
+
+    #[test]
+    fn strict_template_allows_braces_in_the_values() {
+        let mut template = Template::new("subject", "pre_header").unwrap().strict(true);
+        template
+            .register_content_template(
+                "<p>{{file_name}}</p>{{#each files}}<li>{{name}}</li>{{/each}}",
+            )
+            .unwrap();
+        template.add_template_var("file_name", "report {{2024}}.pdf");
+        template
+            .add_template_data("files", &serde_json::json!([{ "name": "{{draft}}.txt" }]))
+            .unwrap();
+
+        let html = template.render_html().unwrap();
+
+        assert!(html.contains("<p>report {{2024}}.pdf</p>"));
+        assert!(html.contains("<li>{{draft}}.txt</li>"));
     }
 }
@@ -0,0 +1,368 @@
+//! Minimal in-process SMTP server used to exercise the senders
+//! against a real socket without needing an external relay.
+//!
+//! It is only built for the tests and with the `mock` feature, so the test
+//! suites of the other crates can send through `SmtpSender` end to end.
+
+use config::email::SmtpCredentials;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Message captured by the test server after a successful DATA command
+#[derive(Debug, Clone, Default)]
+pub struct Captured {
+    /// Sender of the envelope from MAIL FROM
+    pub sender: String,
+    pub recipients: Vec<String>,
+    pub data: String,
+}
+
+/// Behavior switches for the test server
+#[derive(Debug, Clone)]
+pub struct ServerOptions {
+    /// Advertise `AUTH PLAIN LOGIN` in the EHLO response
+    pub advertise_auth: bool,
+    /// Reject mail transactions on connections that didn't authenticate
+    pub require_auth: bool,
+    /// Reject every authentication attempt with `535 5.7.8`
+    pub reject_auth: bool,
+    /// Close the socket without notice after each accepted message
+    pub drop_after_message: bool,
+    /// Response sent after the message body has been received
+    pub data_response: String,
+    /// Delay before responding to the message body
+    pub data_delay: Option<Duration>,
+    /// Recipients that are rejected with `550 5.1.1 Mailbox unavailable`
+    pub rejected_recipients: Vec<String>,
+    /// Close the socket without replying to the first command of a connection
+    /// that already sent a message, the way a relay drops an idle connection
+    pub drop_on_reuse: bool,
+    /// Close the socket without replying once the message body was received,
+    /// the message is still captured the way a relay may have queued it
+    pub drop_before_data_response: bool,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            advertise_auth: true,
+            require_auth: false,
+            reject_auth: false,
+            drop_after_message: false,
+            data_response: "250 2.0.0 Ok: queued".to_string(),
+            data_delay: None,
+            rejected_recipients: vec![],
+            drop_on_reuse: false,
+            drop_before_data_response: false,
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    messages: Vec<Captured>,
+    connections: usize,
+    hello_names: Vec<String>,
+    auth_attempts: usize,
+    transactions: usize,
+    delivering: usize,
+    max_delivering: usize,
+}
+
+pub struct TestServer {
+    port: u16,
+    state: Arc<Mutex<State>>,
+}
+
+impl TestServer {
+    /// Start the server on a random local port
+    pub fn start_with(options: ServerOptions) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let accept_state = state.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    break;
+                };
+
+                let state = accept_state.clone();
+                let options = options.clone();
+
+                thread::spawn(move || {
+                    let _ = handle(stream, &options, &state);
+                });
+            }
+        });
+
+        Self { port, state }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Credentials of the sender that delivers to the server
+    pub fn credentials(&self) -> SmtpCredentials {
+        SmtpCredentials::mock("127.0.0.1", self.port)
+    }
+
+    /// All the messages accepted so far
+    pub fn messages(&self) -> Vec<Captured> {
+        self.state.lock().unwrap().messages.clone()
+    }
+
+    /// Names the clients announced themselves with in EHLO/HELO
+    pub fn hello_names(&self) -> Vec<String> {
+        self.state.lock().unwrap().hello_names.clone()
+    }
+
+    /// Number of times a client tried to authenticate
+    pub fn auth_attempts(&self) -> usize {
+        self.state.lock().unwrap().auth_attempts
+    }
+
+    /// Number of connections opened against the server
+    pub fn connections(&self) -> usize {
+        self.state.lock().unwrap().connections
+    }
+
+    /// Number of mail transactions the clients started, accepted or not
+    pub fn transactions(&self) -> usize {
+        self.state.lock().unwrap().transactions
+    }
+
+    /// Most messages that were waiting for the response to their body at the same time
+    pub fn max_concurrent_deliveries(&self) -> usize {
+        self.state.lock().unwrap().max_delivering
+    }
+}
+
+fn handle(stream: TcpStream, options: &ServerOptions, state: &Mutex<State>) -> std::io::Result<()> {
+    state.lock().unwrap().connections += 1;
+
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut current = Captured::default();
+    let mut authenticated = false;
+    let mut sent_messages = 0;
+
+    writer.write_all(b"220 localhost ESMTP test\r\n")?;
+
+    loop {
+        let mut line = String::new();
+
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+
+        if options.drop_on_reuse && sent_messages > 0 {
+            return Ok(());
+        }
+
+        let command = line.trim_end().to_string();
+        let upper = command.to_uppercase();
+
+        if upper.starts_with("EHLO") || upper.starts_with("HELO") {
+            let name = command.get(5..).unwrap_or_default().trim().to_string();
+            state.lock().unwrap().hello_names.push(name);
+
+            if options.advertise_auth {
+                writer.write_all(b"250-localhost\r\n250 AUTH PLAIN LOGIN\r\n")?;
+            } else {
+                writer.write_all(b"250 localhost\r\n")?;
+            }
+        } else if upper.starts_with("AUTH") {
+            state.lock().unwrap().auth_attempts += 1;
+
+            if options.reject_auth {
+                writer.write_all(b"535 5.7.8 Authentication credentials invalid\r\n")?;
+            } else {
+                authenticated = true;
+                writer.write_all(b"235 2.7.0 Authentication successful\r\n")?;
+            }
+        } else if upper.starts_with("MAIL FROM:") && options.require_auth && !authenticated {
+            writer.write_all(b"530 5.7.0 Authentication required\r\n")?;
+        } else if upper.starts_with("MAIL FROM:") {
+            state.lock().unwrap().transactions += 1;
+            current = Captured {
+                sender: address_of(&command),
+                ..Default::default()
+            };
+            writer.write_all(b"250 2.1.0 Ok\r\n")?;
+        } else if upper.starts_with("RCPT TO:") {
+            let recipient = address_of(&command);
+
+            if options.rejected_recipients.contains(&recipient) {
+                writer.write_all(b"550 5.1.1 Mailbox unavailable\r\n")?;
+            } else {
+                current.recipients.push(recipient);
+                writer.write_all(b"250 2.1.5 Ok\r\n")?;
+            }
+        } else if upper == "DATA" {
+            writer.write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n")?;
+
+            loop {
+                let mut data_line = String::new();
+
+                if reader.read_line(&mut data_line)? == 0 {
+                    return Ok(());
+                }
+
+                if data_line == ".\r\n" {
+                    break;
+                }
+
+                current.data.push_str(&data_line);
+            }
+
+            {
+                let mut state = state.lock().unwrap();
+                state.delivering += 1;
+                state.max_delivering = state.max_delivering.max(state.delivering);
+            }
+
+            if let Some(delay) = options.data_delay {
+                thread::sleep(delay);
+            }
+
+            state.lock().unwrap().delivering -= 1;
+
+            if options.drop_before_data_response {
+                state
+                    .lock()
+                    .unwrap()
+                    .messages
+                    .push(std::mem::take(&mut current));
+
+                return Ok(());
+            }
+
+            if options.data_response.starts_with('2') {
+                state
+                    .lock()
+                    .unwrap()
+                    .messages
+                    .push(std::mem::take(&mut current));
+            }
+
+            writer.write_all(format!("{}\r\n", options.data_response).as_bytes())?;
+
+            sent_messages += 1;
+
+            if options.drop_after_message {
+                return Ok(());
+            }
+        } else if upper == "QUIT" {
+            writer.write_all(b"221 2.0.0 Bye\r\n")?;
+
+            return Ok(());
+        } else {
+            // NOOP, RSET and anything else we don't care about
+            writer.write_all(b"250 2.0.0 Ok\r\n")?;
+        }
+    }
+}
+
+fn address_of(command: &str) -> String {
+    command
+        .split_once('<')
+        .and_then(|(_, rest)| rest.split_once('>'))
+        .map(|(address, _)| address.to_string())
+        .unwrap_or_default()
+}
+
+/// Minimal SOCKS5 proxy without authentication, the relay is always
+/// connected on the local interface on the port from the request.
+pub struct TestProxy {
+    port: u16,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl TestProxy {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(Mutex::new(vec![]));
+
+        let accept_requests = requests.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    break;
+                };
+
+                let requests = accept_requests.clone();
+
+                thread::spawn(move || {
+                    let _ = tunnel(stream, &requests);
+                });
+            }
+        });
+
+        Self { port, requests }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Relays (`host:port`) the clients asked the proxy to connect to
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+fn tunnel(mut client: TcpStream, requests: &Mutex<Vec<String>>) -> std::io::Result<()> {
+    let mut greeting = [0u8; 2];
+    client.read_exact(&mut greeting)?;
+
+    let mut methods = vec![0u8; greeting[1] as usize];
+    client.read_exact(&mut methods)?;
+    client.write_all(&[0x05, 0x00])?;
+
+    // CONNECT request, the relay is always given as a domain name
+    let mut request = [0u8; 5];
+    client.read_exact(&mut request)?;
+
+    let mut host = vec![0u8; request[4] as usize];
+    client.read_exact(&mut host)?;
+
+    let mut port = [0u8; 2];
+    client.read_exact(&mut port)?;
+
+    let port = u16::from_be_bytes(port);
+
+    requests
+        .lock()
+        .unwrap()
+        .push(format!("{}:{}", String::from_utf8_lossy(&host), port));
+
+    let mut relay = match TcpStream::connect(("127.0.0.1", port)) {
+        Ok(relay) => relay,
+        Err(_) => {
+            // Connection refused
+            client.write_all(&[0x05, 0x05, 0x00, 0x01, 0, 0, 0, 0, 0, 0])?;
+
+            return Ok(());
+        }
+    };
+
+    client.write_all(&[0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0, 0])?;
+
+    let mut client_reader = client.try_clone()?;
+    let mut relay_writer = relay.try_clone()?;
+
+    thread::spawn(move || std::io::copy(&mut client_reader, &mut relay_writer));
+
+    std::io::copy(&mut relay, &mut client)?;
+
+    Ok(())
+}
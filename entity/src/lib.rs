@@ -1,3 +1,4 @@
+pub mod email_outbox;
 pub mod file_tokens;
 pub mod files;
 pub mod invitations;
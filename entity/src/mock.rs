@@ -25,6 +25,7 @@ pub async fn create_user<T: super::ConnectionTrait>(
         fingerprint: ActiveValue::Set("".to_string()),
         encrypted_private_key: ActiveValue::NotSet,
         email_verified_at: ActiveValue::Set(Some(Utc::now().timestamp())),
+        email_notifications_disabled_at: ActiveValue::NotSet,
         created_at: ActiveValue::Set(Utc::now().timestamp()),
         updated_at: ActiveValue::Set(Utc::now().timestamp()),
     };
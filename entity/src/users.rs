@@ -26,6 +26,8 @@ pub struct Model {
     pub fingerprint: String,
     pub encrypted_private_key: Option<String>,
     pub email_verified_at: Option<i64>,
+    /// When the user turned off the notification emails, they get them when it is not set
+    pub email_notifications_disabled_at: Option<i64>,
     pub created_at: i64,
     pub updated_at: i64,
 }
@@ -37,6 +39,12 @@ impl Model {
             None => true,
         }
     }
+
+    /// Does the user want to receive the notification emails,
+    /// transactional emails are sent regardless of this.
+    pub fn email_notifications_enabled(&self) -> bool {
+        self.email_notifications_disabled_at.is_none()
+    }
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -76,6 +84,7 @@ mod test {
             fingerprint: "fingerprint".to_string(),
             encrypted_private_key: None,
             email_verified_at: None,
+            email_notifications_disabled_at: None,
             created_at: 0,
             updated_at: 0,
         };
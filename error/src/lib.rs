@@ -55,6 +55,11 @@ pub enum Error {
     HandlebarsRenderError(Box<RenderError>),
     HandlebarsTemplateError(Box<TemplateError>),
     TooManyRequests(String),
+    /// Sending an email didn't finish in time, e.g. the relay stalled mid-message
+    EmailTimeout(String),
+    /// The SMTP relay couldn't be reached, the message tells why, e.g. its hostname
+    /// doesn't resolve, nothing listens on the port or the TLS handshake failed
+    SmtpConnection(String),
 }
 
 impl Error {
@@ -79,6 +84,82 @@ impl Error {
 
         Error::Validation(errors)
     }
+
+    /// Classify the error when it happened while sending an email, so the
+    /// callers can decide whether it makes sense to retry sending.
+    /// Returns `None` for errors that are not related to sending emails.
+    pub fn email_error_kind(&self) -> Option<EmailErrorKind> {
+        match self {
+            Error::SmtpError(err) => Some(EmailErrorKind::from(err.as_ref())),
+            Error::LettreError(_) | Error::AddressError(_) => Some(EmailErrorKind::Permanent),
+            Error::ReqwestError(err) => Some(EmailErrorKind::from(err.as_ref())),
+            Error::EmailTimeout(_) => Some(EmailErrorKind::Transient),
+            Error::SmtpConnection(_) => Some(EmailErrorKind::Connection),
+            _ => None,
+        }
+    }
+
+    /// Code and message of the negative reply the SMTP relay rejected the email with,
+    /// e.g. `(550, "5.1.1 Mailbox unavailable")`, `None` when the relay didn't reply.
+    pub fn smtp_reply(&self) -> Option<(u16, String)> {
+        match self {
+            Error::SmtpError(err) => {
+                let code = err.status()?;
+
+                let message = std::error::Error::source(err.as_ref())
+                    .map(|source| source.to_string())
+                    .unwrap_or_default();
+
+                Some((u16::from(code), message))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Kind of failure that happened while sending an email
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailErrorKind {
+    /// Server temporarily refused the email (4xx), e.g. greylisting, retry later
+    Transient,
+    /// Server or the client rejected the email for good (5xx), don't retry
+    Permanent,
+    /// Server couldn't be reached or the connection broke, retry later
+    Connection,
+}
+
+impl EmailErrorKind {
+    /// Does it make sense to try sending the email again
+    pub fn is_retryable(&self) -> bool {
+        !matches!(self, EmailErrorKind::Permanent)
+    }
+}
+
+impl From<&SmtpError> for EmailErrorKind {
+    fn from(source: &SmtpError) -> EmailErrorKind {
+        if source.is_transient() {
+            EmailErrorKind::Transient
+        } else if source.is_permanent() || source.is_client() {
+            EmailErrorKind::Permanent
+        } else {
+            // Network, tls, timeouts, shutdown transport and unparsable responses
+            EmailErrorKind::Connection
+        }
+    }
+}
+
+impl From<&ReqwestError> for EmailErrorKind {
+    fn from(source: &ReqwestError) -> EmailErrorKind {
+        match source.status() {
+            // Rate limited or the endpoint is having trouble
+            Some(status) if status.is_server_error() || status.as_u16() == 429 => {
+                EmailErrorKind::Transient
+            }
+            Some(_) => EmailErrorKind::Permanent,
+            None if source.is_builder() => EmailErrorKind::Permanent,
+            None => EmailErrorKind::Connection,
+        }
+    }
 }
 
 impl std::fmt::Display for Error {
@@ -404,6 +485,16 @@ impl From<&Error> for ErrorResponse {
                 message: message.to_string(),
                 context: None,
             },
+            Error::EmailTimeout(message) => ErrorResponse {
+                status: 500,
+                message: message.to_string(),
+                context: None,
+            },
+            Error::SmtpConnection(message) => ErrorResponse {
+                status: 500,
+                message: message.to_string(),
+                context: None,
+            },
         }
     }
 }
@@ -0,0 +1,35 @@
+//! Subcommands that run instead of the server, see [config::command::Command].
+
+use config::{command::Command, Config};
+use error::{AppResult, Error};
+
+/// Run the subcommand and return the exit code of the process
+pub async fn run(config: &Config, command: &Command) -> i32 {
+    let result = match command {
+        Command::SendTestEmail { to } => send_test_email(config, to).await,
+    };
+
+    match result {
+        Ok(message) => {
+            println!("{message}");
+
+            0
+        }
+        Err(e) => {
+            eprintln!("Failed: {e}");
+
+            1
+        }
+    }
+}
+
+/// Send the same test email as the admin settings do with the configured mailer
+pub async fn send_test_email(config: &Config, to: &str) -> AppResult<String> {
+    let sender = email::Sender::new(config)?.ok_or_else(|| {
+        Error::BadRequest("email_not_configured, set MAILER_TYPE to smtp or dryrun".to_string())
+    })?;
+
+    admin::emails::test_email::send(&sender, config, to).await?;
+
+    Ok(format!("Test email sent successfully to {to}"))
+}
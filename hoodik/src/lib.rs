@@ -1,4 +1,5 @@
 mod client;
+pub mod commands;
 pub mod server;
 
 pub use config::Config;
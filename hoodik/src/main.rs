@@ -19,22 +19,32 @@ async fn main() -> AppResult<()> {
         "Hoodik is a simple, fast and end to end encrypted cloud storage.",
     );
 
+    // Subcommands run on their own, without the database and the server
+    if let Some(command) = config.command.clone() {
+        env_logger::init();
+
+        config.emit_warnings();
+
+        std::process::exit(hoodik::commands::run(&config, &command).await);
+    }
+
     config.app.ensure_data_dir(None);
 
     config.announce();
 
+    // Init logger before creating the context so the startup
+    // diagnostics of the email sender are visible
+    env_logger::init();
+
+    // Emit warnings after logger is initialized
+    config.emit_warnings();
+
     // Create context from the config
     let context = Context::new(config).await?;
 
     // Run database migrations
     Migrator::up(&context.db, None).await?;
 
-    // Init logger
-    env_logger::init();
-
-    // Emit warnings after logger is initialized
-    context.config.emit_warnings();
-
     // Start the server
     hoodik::server::engage(context).await
 }
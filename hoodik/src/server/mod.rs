@@ -10,7 +10,8 @@ use actix_web::{
     middleware::Logger,
     web, App, HttpServer,
 };
-use context::Context;
+use config::email::EmailConfig;
+use context::{Context, SenderContract};
 use error::{AppResult, Error};
 
 pub mod client;
@@ -75,20 +76,54 @@ pub async fn engage(context: Context) -> AppResult<()> {
     let disabled = context.config.ssl.disabled;
     let app_url = context.config.get_app_url();
     let config = context.config.ssl.build_rustls_config(vec![app_url])?;
-    
+
+    // Send the queued emails in the background
+    let outbox = context::outbox::spawn_worker(context.clone());
+    let shutdown_grace = context.config.get_mail_shutdown_grace();
+
+    spawn_connection_test(&context);
+
     let server = HttpServer::new(move || {
         app(context.clone()).wrap(Logger::new(
             "%a \"%r\" %s %b \"%{Referer}i\" \"%{User-Agent}i\" %T",
         ))
     });
 
-    if disabled {
+    let result = if disabled {
         server.bind(&bind_address)?.run().await.map_err(Error::from)
     } else {
         server
-        .bind_rustls(&bind_address, config)?
-        .run()
-        .await
-        .map_err(Error::from)
+            .bind_rustls(&bind_address, config)?
+            .run()
+            .await
+            .map_err(Error::from)
+    };
+
+    // The server stopped on a signal, let the outbox finish the email it is sending
+    outbox.shutdown(shutdown_grace).await;
+
+    result
+}
+
+/// Test the connection to the relay the sender couldn't test on startup, because of
+/// SMTP_VERIFY_ON_STARTUP or a proxied relay, so a slow relay doesn't hold up the server
+fn spawn_connection_test(context: &Context) {
+    let EmailConfig::Smtp(credentials) = &context.config.mailer else {
+        return;
+    };
+
+    if credentials.verify_on_startup && credentials.proxy.is_none() {
+        return;
     }
+
+    let Some(sender) = context.sender.clone() else {
+        return;
+    };
+
+    actix_web::rt::spawn(async move {
+        match sender.test_connection().await {
+            Ok(()) => log::info!("SMTP connection tested in the background"),
+            Err(e) => log::warn!("SMTP connection test in the background failed: {e}"),
+        }
+    });
 }
@@ -0,0 +1,30 @@
+use config::{email::EmailConfig, Config};
+use hoodik::commands;
+
+#[actix_web::test]
+async fn test_send_test_email_with_dry_run() {
+    let mut config = Config::mock_with_env();
+    config.mailer = EmailConfig::DryRun;
+
+    let message = commands::send_test_email(&config, "john@doe.com")
+        .await
+        .unwrap();
+
+    assert!(message.contains("john@doe.com"));
+
+    assert!(commands::send_test_email(&config, "not an email")
+        .await
+        .is_err());
+}
+
+#[actix_web::test]
+async fn test_send_test_email_without_mailer() {
+    let mut config = Config::mock_with_env();
+    config.mailer = EmailConfig::None;
+
+    let error = commands::send_test_email(&config, "john@doe.com")
+        .await
+        .unwrap_err();
+
+    assert!(error.to_string().contains("email_not_configured"));
+}
@@ -1,4 +1,4 @@
-use config::{email::EmailConfig, Config};
+use config::Config;
 use email::{contract::SenderContract, Sender};
 
 #[actix_web::test]
@@ -6,10 +6,10 @@ use email::{contract::SenderContract, Sender};
 async fn test_send_email_via_smtp() {
     let config = Config::mock_with_env();
 
-    let _smtp_credentials = match &config.mailer {
-        EmailConfig::Smtp(c) => c,
-        _ => panic!("Error loading email config, no smtp setup"),
-    };
+    let _smtp_credentials = config
+        .mailer
+        .smtp_credentials()
+        .expect("Error loading email config, no smtp setup");
 
     let sender = Sender::new(&config).unwrap().unwrap();
 
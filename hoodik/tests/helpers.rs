@@ -26,6 +26,41 @@ pub(crate) fn extract_cookies(
     (jwt, refresh)
 }
 
+/// Register the first user, who becomes the admin, and return the session cookie
+#[allow(dead_code)]
+pub(crate) async fn register_admin<S, B>(app: &S) -> actix_web::cookie::Cookie<'static>
+where
+    S: actix_web::dev::Service<
+        actix_http::Request,
+        Response = actix_web::dev::ServiceResponse<B>,
+        Error = actix_web::Error,
+    >,
+{
+    let private = cryptfns::rsa::private::generate().unwrap();
+    let public = cryptfns::rsa::public::from_private(&private).unwrap();
+    let public_string = cryptfns::rsa::public::to_string(&public).unwrap();
+    let fingerprint = cryptfns::rsa::fingerprint(public).unwrap();
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/api/auth/register")
+        .set_json(&auth::data::create_user::CreateUser {
+            email: Some("john@doe.com".to_string()),
+            password: Some("not-4-weak-password-for-god-sakes!".to_string()),
+            secret: None,
+            token: None,
+            pubkey: Some(public_string),
+            fingerprint: Some(fingerprint),
+            encrypted_private_key: Some("encrypted-gibberish".to_string()),
+            invitation_id: None,
+        })
+        .to_request();
+
+    let resp = actix_web::test::call_service(app, req).await;
+    let (jwt, _) = extract_cookies(resp.headers());
+
+    jwt.unwrap()
+}
+
 /// Helper to create some mock file for uploading
 #[allow(dead_code)]
 pub(crate) fn create_byte_chunks() -> (Vec<Vec<u8>>, i64, String) {
@@ -0,0 +1,301 @@
+use config::email::{EmailConfig, SmtpCredentials};
+use context::{
+    outbox::{self, OutboxRepository},
+    preferences, Context, SenderContract,
+};
+use email::{senders::dryrun::DryRunSender, template::EmailCategory, Sender};
+use entity::{
+    email_outbox::{STATUS_FAILED, STATUS_PENDING, STATUS_SENDING, STATUS_SENT, STATUS_SKIPPED},
+    users, ActiveModelTrait, ActiveValue,
+};
+
+#[actix_web::test]
+async fn test_queued_email_is_sent_by_the_outbox() {
+    let context = Context::add_mock_sender(Context::mock_sqlite().await);
+    let sender = context.sender.as_ref().unwrap();
+
+    let mut template = sender
+        .template("Queued email subject", "Queued email pre header")
+        .unwrap();
+
+    template
+        .register_content_template("<p>{{ name }}</p>")
+        .unwrap();
+    template.add_template_var("name", "John");
+
+    let id = context
+        .enqueue_email(template.to("john@doe.com").unwrap())
+        .await
+        .unwrap();
+
+    assert!(!sender.has("Queued email subject"));
+
+    let sent = outbox::drain(&context).await.unwrap();
+
+    assert_eq!(sent, 1);
+    assert!(sender.has("Queued email subject"));
+
+    let email = OutboxRepository::new(&context.db).get(id).await.unwrap();
+
+    assert_eq!(email.status, STATUS_SENT);
+    assert_eq!(email.attempts, 1);
+    assert!(email.sent_at.is_some());
+
+    // Sent emails are not picked up again
+    assert_eq!(outbox::drain(&context).await.unwrap(), 0);
+}
+
+#[actix_web::test]
+async fn test_claimed_email_is_not_sent_by_another_drain() {
+    let context = Context::add_mock_sender(Context::mock_sqlite().await);
+    let sender = context.sender.as_ref().unwrap();
+
+    let template = sender
+        .template("Claimed email subject", "Claimed email pre header")
+        .unwrap()
+        .to("john@doe.com")
+        .unwrap();
+
+    let id = context.enqueue_email(template).await.unwrap();
+
+    let repository = OutboxRepository::new(&context.db);
+    let due = repository.due(10).await.unwrap();
+
+    // Two drains found the same email, only the first one gets to send it
+    assert!(repository.claim(&due[0]).await.unwrap());
+    assert!(!repository.claim(&due[0]).await.unwrap());
+
+    assert_eq!(outbox::drain(&context).await.unwrap(), 0);
+    assert!(!sender.has("Claimed email subject"));
+
+    let email = repository.get(id).await.unwrap();
+
+    assert_eq!(email.status, STATUS_SENDING);
+    assert!(email.next_attempt_at >= email.created_at + outbox::CLAIM_LEASE_SECONDS);
+}
+
+#[actix_web::test]
+async fn test_failed_queued_email_is_retried_with_backoff() {
+    // Nothing is listening on the port, so the connection fails
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+
+    let mut credentials = SmtpCredentials::mock("127.0.0.1", port);
+    credentials.verify_on_startup = false;
+
+    let mut context = Context::mock_sqlite().await;
+    context.config.mailer = EmailConfig::Smtp(Box::new(credentials));
+    context.sender = Sender::new(&context.config).unwrap();
+
+    let template = context
+        .sender
+        .as_ref()
+        .unwrap()
+        .template("Failing email subject", "Failing email pre header")
+        .unwrap()
+        .to("john@doe.com")
+        .unwrap();
+
+    let id = context.enqueue_email(template).await.unwrap();
+
+    let sent = outbox::drain(&context).await.unwrap();
+
+    assert_eq!(sent, 0);
+
+    let repository = OutboxRepository::new(&context.db);
+    let email = repository.get(id).await.unwrap();
+
+    assert_eq!(email.status, STATUS_PENDING);
+    assert_eq!(email.attempts, 1);
+    assert!(email.last_error.is_some());
+    assert!(email.next_attempt_at >= email.created_at + outbox::backoff(1));
+
+    // It is not due again until the backoff passes
+    assert!(repository.due(10).await.unwrap().is_empty());
+}
+
+#[actix_web::test]
+async fn test_permanently_failing_queued_email_is_not_retried() {
+    let context = Context::add_mock_sender(Context::mock_sqlite().await);
+    let sender = context.sender.as_ref().unwrap();
+
+    // Without a recipient the email can never be sent
+    let template = sender
+        .template("Failing email subject", "Failing email pre header")
+        .unwrap();
+
+    let id = context.enqueue_email(template).await.unwrap();
+
+    assert_eq!(outbox::drain(&context).await.unwrap(), 0);
+
+    let email = OutboxRepository::new(&context.db).get(id).await.unwrap();
+
+    assert_eq!(email.status, STATUS_FAILED);
+    assert_eq!(email.attempts, 1);
+    assert!(email.last_error.is_some());
+}
+
+#[actix_web::test]
+async fn test_notifications_are_skipped_for_opted_out_users() {
+    let context = Context::add_mock_sender(Context::mock_sqlite().await);
+    let sender = context.sender.as_ref().unwrap();
+
+    let user = entity::mock::create_user(&context.db, "john@doe.com", None).await;
+
+    users::ActiveModel {
+        id: ActiveValue::Unchanged(user.id),
+        email_notifications_disabled_at: ActiveValue::Set(Some(1)),
+        ..Default::default()
+    }
+    .update(&context.db)
+    .await
+    .unwrap();
+
+    let notification = sender
+        .template("Notification subject", "Notification pre header")
+        .unwrap()
+        .to("John <John@doe.com>")
+        .unwrap()
+        .category(EmailCategory::Notification);
+
+    let transactional = sender
+        .template("Transactional subject", "Transactional pre header")
+        .unwrap()
+        .to("john@doe.com")
+        .unwrap();
+
+    let notification = context.enqueue_email(notification).await.unwrap();
+    let transactional = context.enqueue_email(transactional).await.unwrap();
+
+    assert_eq!(outbox::drain(&context).await.unwrap(), 1);
+
+    assert!(!sender.has("Notification subject"));
+    assert!(sender.has("Transactional subject"));
+
+    let repository = OutboxRepository::new(&context.db);
+
+    assert_eq!(
+        repository.get(notification).await.unwrap().status,
+        STATUS_SKIPPED
+    );
+    assert_eq!(
+        repository.get(transactional).await.unwrap().status,
+        STATUS_SENT
+    );
+
+    // Skipped emails are not picked up again
+    assert_eq!(outbox::drain(&context).await.unwrap(), 0);
+}
+
+#[actix_web::test]
+async fn test_sent_notifications_are_skipped_for_opted_out_users() {
+    let context = Context::add_mock_sender(Context::mock_sqlite().await);
+    let sender = context.sender.as_ref().unwrap();
+
+    // The address is stored with a different case than the one it is sent to
+    let user = entity::mock::create_user(&context.db, "Jane@Doe.com", None).await;
+
+    users::ActiveModel {
+        id: ActiveValue::Unchanged(user.id),
+        email_notifications_disabled_at: ActiveValue::Set(Some(1)),
+        ..Default::default()
+    }
+    .update(&context.db)
+    .await
+    .unwrap();
+
+    let notification = sender
+        .template("Sent notification subject", "Notification pre header")
+        .unwrap()
+        .to("Jane <jane@doe.com>")
+        .unwrap()
+        .category(EmailCategory::Notification);
+
+    let transactional = sender
+        .template("Sent transactional subject", "Transactional pre header")
+        .unwrap()
+        .to("jane@doe.com")
+        .unwrap();
+
+    context.send_email(notification).await.unwrap();
+    context.send_email(transactional).await.unwrap();
+
+    assert!(!sender.has("Sent notification subject"));
+    assert!(sender.has("Sent transactional subject"));
+}
+
+#[actix_web::test]
+async fn test_dry_run_notifications_are_skipped_for_opted_out_users() {
+    let dry_run = DryRunSender::new("Hoodik").unwrap();
+    let context = Context::mock_sqlite()
+        .await
+        .with_sender(Box::new(dry_run.clone()));
+    let sender = context.sender.as_ref().unwrap();
+
+    let user = entity::mock::create_user(&context.db, "jane@doe.com", None).await;
+
+    users::ActiveModel {
+        id: ActiveValue::Unchanged(user.id),
+        email_notifications_disabled_at: ActiveValue::Set(Some(1)),
+        ..Default::default()
+    }
+    .update(&context.db)
+    .await
+    .unwrap();
+
+    let notification = sender
+        .template("Dry run notification subject", "Notification pre header")
+        .unwrap()
+        .to("jane@doe.com")
+        .unwrap()
+        .category(EmailCategory::Notification);
+
+    // The dry run gets the email marked the same way the real senders get it
+    let notification = preferences::apply(&context.db, notification).await.unwrap();
+
+    assert!(notification.skip_send());
+    assert_eq!(dry_run.send(vec![notification.clone()]).await.unwrap(), 1);
+
+    context.send_email(notification).await.unwrap();
+}
+
+#[actix_web::test]
+async fn test_worker_sends_queued_emails_and_stops_on_shutdown() {
+    let context = Context::add_mock_sender(Context::mock_sqlite().await);
+    let sender = context.sender.as_ref().unwrap();
+
+    let template = sender
+        .template("Worker email subject", "Worker email pre header")
+        .unwrap()
+        .to("john@doe.com")
+        .unwrap();
+
+    let id = context.enqueue_email(template).await.unwrap();
+
+    let worker = outbox::spawn_worker(context.clone());
+
+    // The first drain runs right after the worker starts
+    for _ in 0..50 {
+        if sender.has("Worker email subject") {
+            break;
+        }
+
+        actix_web::rt::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+
+    assert!(sender.has("Worker email subject"));
+
+    // An idle worker stops right away instead of waiting out the grace period
+    let started = std::time::Instant::now();
+
+    worker.shutdown(std::time::Duration::from_secs(5)).await;
+
+    assert!(started.elapsed() < std::time::Duration::from_secs(1));
+
+    let email = OutboxRepository::new(&context.db).get(id).await.unwrap();
+
+    assert_eq!(email.status, STATUS_SENT);
+}
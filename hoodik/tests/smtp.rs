@@ -0,0 +1,45 @@
+use config::Config;
+use email::{
+    senders::smtp::SmtpSender,
+    test_server::{ServerOptions, TestServer},
+    Sender,
+};
+
+#[actix_web::test]
+async fn test_bulk_test_email_is_delivered_over_smtp() {
+    let server = TestServer::start_with(ServerOptions {
+        rejected_recipients: vec!["bounce@doe.com".to_string()],
+        ..Default::default()
+    });
+
+    let config = Config::mock_with_env();
+    let smtp = SmtpSender::new(&server.credentials()).unwrap();
+    let sender = Sender::with_inner(&config, Box::new(smtp));
+
+    let to = vec![
+        "first@doe.com".to_string(),
+        "bounce@doe.com".to_string(),
+        "second@doe.com".to_string(),
+    ];
+
+    let outcomes = admin::emails::test_email::send_bulk(&sender, &config, &to)
+        .await
+        .unwrap();
+
+    assert_eq!(outcomes.len(), 3);
+    assert!(outcomes[0].sent);
+    assert!(outcomes[2].sent);
+
+    // The rejected recipient doesn't stop the rest of the batch
+    assert!(!outcomes[1].sent);
+    assert_eq!(outcomes[1].relay_response.as_ref().unwrap().code, 550);
+
+    let messages = server.messages();
+
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0].recipients, vec!["first@doe.com".to_string()]);
+    assert_eq!(messages[1].recipients, vec!["second@doe.com".to_string()]);
+    assert!(messages[0]
+        .data
+        .contains("Subject: Test Email - SMTP Configuration"));
+}
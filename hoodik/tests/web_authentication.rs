@@ -166,6 +166,9 @@ async fn test_register_and_verify_user_email() {
 
     assert_eq!(resp.status(), StatusCode::CREATED);
 
+    // The activation email is queued, it is sent once the outbox is drained
+    context::outbox::drain(&context).await.unwrap();
+
     let id = context
         .sender
         .as_ref()
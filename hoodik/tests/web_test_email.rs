@@ -0,0 +1,224 @@
+#[path = "./helpers.rs"]
+mod helpers;
+
+use actix_web::test;
+use config::email::SmtpCredentials;
+use context::Context;
+use email::{
+    senders::{mock::MockSender, smtp::SmtpSender},
+    test_server::{ServerOptions, TestServer},
+    Sender,
+};
+use hoodik::server;
+
+#[actix_web::test]
+async fn test_test_email_is_sent_to_the_admin() {
+    let sender = MockSender::new();
+    let context = Context::mock_sqlite()
+        .await
+        .with_sender(Box::new(sender.clone()));
+
+    let app = test::init_service(server::app(context.clone())).await;
+
+    // The first registered user is the admin
+    let jwt = helpers::register_admin(&app).await;
+
+    let req = test::TestRequest::post()
+        .uri("/api/admin/settings/test-email")
+        .cookie(jwt)
+        .to_request();
+
+    let resp = test::call_service(&app, req).await;
+
+    assert_eq!(resp.status(), 200);
+
+    let sent = sender.sent();
+    let email = sent.last().unwrap();
+
+    assert_eq!(email.payload().subject, "Test Email - SMTP Configuration");
+    assert_eq!(email.recipients(), vec!["john@doe.com".to_string()]);
+
+    // Nobody is expected to reply to the test email
+    let formatted = String::from_utf8(email.message().unwrap().formatted()).unwrap();
+
+    assert!(formatted.contains("Auto-Submitted: auto-generated\r\n"));
+    assert!(formatted.contains("X-Auto-Response-Suppress: All\r\n"));
+}
+
+#[actix_web::test]
+async fn test_test_email_subject_is_prefixed() {
+    let mut config = config::Config::mock_with_env();
+    config.app.mail_subject_prefix = Some("[Acme]".to_string());
+
+    let mock = MockSender::new();
+    let sender = Sender::with_inner(&config, Box::new(mock.clone()));
+
+    admin::emails::test_email::send(&sender, &config, "john@doe.com")
+        .await
+        .unwrap();
+
+    assert_eq!(
+        mock.sent()[0].payload().subject,
+        "[Acme] Test Email - SMTP Configuration"
+    );
+}
+
+#[actix_web::test]
+async fn test_bulk_test_email_reports_every_address() {
+    let sender = MockSender::new();
+    let context = Context::mock_sqlite()
+        .await
+        .with_sender(Box::new(sender.clone()));
+
+    let app = test::init_service(server::app(context.clone())).await;
+
+    let jwt = helpers::register_admin(&app).await;
+
+    // Invalid addresses are rejected before anything is sent
+    let req = test::TestRequest::post()
+        .uri("/api/admin/settings/test-email-bulk")
+        .cookie(jwt.clone())
+        .set_json(serde_json::json!({ "to": ["jane@gmail.com", "not-an-email"] }))
+        .to_request();
+
+    let resp = test::call_service(&app, req).await;
+
+    assert_eq!(resp.status(), 422);
+
+    let req = test::TestRequest::post()
+        .uri("/api/admin/settings/test-email-bulk")
+        .cookie(jwt.clone())
+        .set_json(serde_json::json!({ "to": ["jane@gmail.com", "jane@outlook.com"] }))
+        .to_request();
+
+    let results: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+    assert_eq!(results[0]["address"], "jane@gmail.com");
+    assert_eq!(results[0]["sent"], true);
+    assert_eq!(results[1]["address"], "jane@outlook.com");
+    assert_eq!(results[1]["sent"], true);
+
+    // The activation email of the registration is queued, only the test emails were sent
+    let test_emails = sender.sent();
+
+    assert_eq!(test_emails.len(), 2);
+
+    assert_eq!(
+        test_emails[0].recipients(),
+        vec!["jane@gmail.com".to_string()]
+    );
+    assert_eq!(
+        test_emails[1].recipients(),
+        vec!["jane@outlook.com".to_string()]
+    );
+
+    // The cooldown is shared with the single test email
+    let req = test::TestRequest::post()
+        .uri("/api/admin/settings/test-email")
+        .cookie(jwt)
+        .to_request();
+
+    let resp = test::call_service(&app, req).await;
+
+    assert_eq!(resp.status(), 429);
+}
+
+#[actix_web::test]
+async fn test_email_status_probes_the_relay() {
+    let server = TestServer::start_with(ServerOptions::default());
+    let smtp = SmtpSender::new(&server.credentials()).unwrap();
+    let context = Context::mock_sqlite().await.with_sender(Box::new(smtp));
+
+    let app = test::init_service(server::app(context.clone())).await;
+
+    let jwt = helpers::register_admin(&app).await;
+
+    let req = test::TestRequest::get()
+        .uri("/api/admin/settings/email-status")
+        .cookie(jwt)
+        .to_request();
+
+    let status: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+    assert_eq!(status["connected"], true);
+    assert!(status["error"].is_null());
+}
+
+#[actix_web::test]
+async fn test_email_status_gives_up_on_a_silent_relay() {
+    // The relay accepts the connection but never greets
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let mut credentials = SmtpCredentials::mock("127.0.0.1", port);
+    credentials.verify_on_startup = false;
+    credentials.send_timeout = std::time::Duration::from_millis(300);
+
+    let smtp = SmtpSender::new(&credentials).unwrap();
+    let context = Context::mock_sqlite().await.with_sender(Box::new(smtp));
+
+    let app = test::init_service(server::app(context.clone())).await;
+
+    let jwt = helpers::register_admin(&app).await;
+
+    let req = test::TestRequest::get()
+        .uri("/api/admin/settings/email-status")
+        .cookie(jwt)
+        .to_request();
+
+    let status: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+    assert_eq!(status["connected"], false);
+    assert!(status["error"]
+        .as_str()
+        .unwrap()
+        .contains("smtp_connection_test_timed_out"));
+
+    drop(listener);
+}
+
+#[actix_web::test]
+async fn test_email_metrics_are_scraped_with_the_token() {
+    let mut context = Context::mock_sqlite().await;
+    context.config.app.mail_metrics_token = Some("scrape-secret".to_string());
+
+    let app = test::init_service(server::app(context.clone())).await;
+
+    let req = test::TestRequest::get()
+        .uri("/api/admin/settings/email-metrics")
+        .insert_header(("Authorization", "Bearer scrape-secret"))
+        .to_request();
+
+    let resp = test::call_service(&app, req).await;
+
+    assert_eq!(resp.status(), 200);
+
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+
+    assert!(body.contains("hoodik_emails_sent_total"));
+
+    let req = test::TestRequest::get()
+        .uri("/api/admin/settings/email-metrics")
+        .insert_header(("Authorization", "Bearer wrong-secret"))
+        .to_request();
+
+    let resp = test::call_service(&app, req).await;
+
+    assert_eq!(resp.status(), 401);
+
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+
+    assert!(body.contains("invalid_metrics_token"));
+
+    let req = test::TestRequest::get()
+        .uri("/api/admin/settings/email-metrics")
+        .to_request();
+
+    let resp = test::call_service(&app, req).await;
+
+    assert_eq!(resp.status(), 401);
+
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+
+    assert!(body.contains("missing_metrics_token"));
+}
@@ -10,6 +10,8 @@ pub(crate) mod m20230429_101730_create_file_tokens;
 pub(crate) mod m20230521_074334_create_links;
 pub(crate) mod m20230612_074334_create_invitations;
 pub(crate) mod m20240915_074334_alter_files_add_hashes;
+pub(crate) mod m20241014_090000_create_email_outbox;
+pub(crate) mod m20241021_090000_alter_users_add_email_notifications;
 
 pub struct Migrator;
 
@@ -27,6 +29,8 @@ impl MigratorTrait for Migrator {
             Box::new(m20230521_074334_create_links::Migration),
             Box::new(m20230612_074334_create_invitations::Migration),
             Box::new(m20240915_074334_alter_files_add_hashes::Migration),
+            Box::new(m20241014_090000_create_email_outbox::Migration),
+            Box::new(m20241021_090000_alter_users_add_email_notifications::Migration),
         ]
     }
 }